@@ -0,0 +1,515 @@
+use std::collections::HashMap;
+use std::fs;
+use std::panic::{self, AssertUnwindSafe};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::thread;
+
+use crate::output;
+use crate::timeline::Segment;
+use crate::Cli;
+
+/// Extensions of files in source directories that are summarized,
+/// if user doesn't set any glob pattern.
+const MEDIA_EXTENSIONS: [&str; 18] = [
+    "mp4", "m4v", "mkv", "webm", "mov", "avi", "wmv", "flv", "mpeg", "mpg", "ts", "m4a", "mp3",
+    "ogg", "opus", "flac", "wav", "aac",
+];
+
+/// How long a video is, and how long it is summarized. Durations are in seconds.
+pub struct Summary {
+    pub duration: f32,
+    pub summarized_duration: f32,
+    pub segments: Vec<Segment>,
+}
+impl Summary {
+    pub fn new(segments: &[Segment]) -> Summary {
+        Summary {
+            duration: segments.iter().map(|s| s.duration()).sum(),
+            summarized_duration: segments.iter().map(|s| s.summarized_duration()).sum(),
+            segments: segments.to_vec(),
+        }
+    }
+}
+
+/// Single video to summarize.
+struct Job {
+    input: PathBuf,
+    output: PathBuf,
+}
+
+/// Summarize all source videos, including videos in source directories,
+/// and print table with results. Failure of one video doesn't stop the others.
+///
+/// Returns false if any of the videos failed.
+pub fn run(args: &Cli) -> bool {
+    if !args.output.as_os_str().is_empty() {
+        eprintln!("Output file can be set only for single source video. Use --output-dir and --name-template instead.");
+        return false;
+    }
+    if args.input.iter().any(|input| input.to_str() == Some("-")) {
+        eprintln!("Video can be piped in only if it's the only source video.");
+        return false;
+    }
+    if args.plot.is_some()
+        || args.report.is_some()
+        || args.mpv_script.is_some()
+        || args.command.is_some()
+    {
+        eprintln!("Options --plot, --report, --mpv and play work only with single source video.");
+        return false;
+    }
+
+    let jobs = match collect_jobs(args) {
+        Ok(jobs) => jobs,
+        Err(e) => {
+            eprintln!("{}", e);
+            return false;
+        }
+    };
+    if jobs.is_empty() {
+        eprintln!("No videos to summarize were found.");
+        return true;
+    }
+
+    // Workers take videos one by one, until there are none left
+    let queue = Mutex::new(jobs.iter().enumerate());
+    let results: Mutex<Vec<Option<Result<Summary, String>>>> =
+        Mutex::new(jobs.iter().map(|_| None).collect());
+    thread::scope(|scope| {
+        for _ in 0..args.parallel.clamp(1, jobs.len()) {
+            scope.spawn(|| loop {
+                let next = queue.lock().unwrap().next();
+                let (index, job) = match next {
+                    Some(next) => next,
+                    None => break,
+                };
+                if !args.quiet {
+                    eprintln!(
+                        "[{}/{}] Summarizing {}",
+                        index + 1,
+                        jobs.len(),
+                        job.input.display()
+                    );
+                }
+                let result = summarize_isolated(args, &job.input, &job.output);
+                if let Err(e) = &result {
+                    eprintln!("Failed to summarize {}: {}", job.input.display(), e);
+                }
+                results.lock().unwrap()[index] = Some(result);
+            });
+        }
+    });
+
+    let results: Vec<Result<Summary, String>> = results
+        .into_inner()
+        .unwrap()
+        .into_iter()
+        .map(|result| result.expect("Video was not summarized."))
+        .collect();
+    print_table(&jobs, &results);
+    results.iter().all(|result| result.is_ok())
+}
+
+/// Summarize single video. Everything that goes wrong (including panics)
+/// is returned as an error, so other videos can continue.
+pub fn summarize_isolated(args: &Cli, input: &Path, output: &Path) -> Result<Summary, String> {
+    if let Some(output_directory) = output.parent() {
+        fs::create_dir_all(output_directory).map_err(|e| {
+            format!(
+                "Failed to create output directory {}: {}",
+                output_directory.display(),
+                e
+            )
+        })?;
+    }
+    match panic::catch_unwind(AssertUnwindSafe(|| {
+        crate::summarize(args, input, output, false)
+    })) {
+        Ok(result) => result,
+        Err(panic) => Err(panic
+            .downcast_ref::<&str>()
+            .map(|message| message.to_string())
+            .or_else(|| panic.downcast_ref::<String>().cloned())
+            .unwrap_or_else(|| String::from("unknown error"))),
+    }
+}
+
+/// Find all videos to summarize and decide where to write them.
+/// Videos that are outputs of other videos (from previous runs) are skipped.
+fn collect_jobs(args: &Cli) -> Result<Vec<Job>, String> {
+    let mut jobs: Vec<Job> = Vec::new();
+    for input in &args.input {
+        if input.is_dir() {
+            let mut files: Vec<PathBuf> = Vec::new();
+            find_files(input, Path::new(""), &mut files)
+                .map_err(|e| format!("Failed to read directory {}: {}", input.display(), e))?;
+            for relative_path in files {
+                if !matches_filter(&args.glob, &relative_path) {
+                    continue;
+                }
+                let input_file = input.join(&relative_path);
+                let output_directory = match &args.output_dir {
+                    Some(output_dir) => output_dir.join(relative_path.parent().unwrap()),
+                    None => input_file.parent().unwrap().to_path_buf(),
+                };
+                jobs.push(Job {
+                    output: output_directory.join(output_name(args, &input_file)),
+                    input: input_file,
+                });
+            }
+        } else if input.is_file() {
+            let output_directory = match &args.output_dir {
+                Some(output_dir) => output_dir.clone(),
+                None => input.parent().unwrap().to_path_buf(),
+            };
+            jobs.push(Job {
+                output: output_directory.join(output_name(args, input)),
+                input: input.clone(),
+            });
+        } else {
+            return Err(format!("Source video {} does not exist.", input.display()));
+        }
+    }
+
+    let outputs: Vec<PathBuf> = jobs.iter().map(|job| job.output.clone()).collect();
+    jobs.retain(|job| !outputs.contains(&job.input));
+
+    let mut inputs_by_output: HashMap<&Path, &Path> = HashMap::new();
+    for job in &jobs {
+        if let Some(other_input) = inputs_by_output.insert(&job.output, &job.input) {
+            return Err(format!(
+                "Both {} and {} would be written to {}. Use different --name-template.",
+                other_input.display(),
+                job.input.display(),
+                job.output.display()
+            ));
+        }
+    }
+    Ok(jobs)
+}
+
+/// Recursively find all files in directory. Paths are relative
+/// to the directory, and sorted.
+pub fn find_files(root: &Path, relative: &Path, files: &mut Vec<PathBuf>) -> std::io::Result<()> {
+    let mut entries: Vec<PathBuf> = fs::read_dir(root.join(relative))?
+        .map(|entry| entry.map(|entry| relative.join(entry.file_name())))
+        .collect::<std::io::Result<_>>()?;
+    entries.sort();
+    for entry in entries {
+        if root.join(&entry).is_dir() {
+            find_files(root, &entry, files)?;
+        } else {
+            files.push(entry);
+        }
+    }
+    Ok(())
+}
+
+/// Whether file in source directory should be summarized.
+pub fn matches_filter(patterns: &[String], relative_path: &Path) -> bool {
+    if patterns.is_empty() {
+        return relative_path
+            .extension()
+            .and_then(|extension| extension.to_str())
+            .map(|extension| MEDIA_EXTENSIONS.contains(&extension.to_lowercase().as_str()))
+            .unwrap_or(false);
+    }
+    let path: Vec<String> = relative_path
+        .components()
+        .map(|component| component.as_os_str().to_string_lossy().to_string())
+        .collect();
+    let filename = path.last().cloned().unwrap_or_default();
+    let path = path.join("/");
+    patterns.iter().any(|pattern| {
+        let text = if pattern.contains('/') {
+            &path
+        } else {
+            &filename
+        };
+        glob_matches(pattern.as_bytes(), text.as_bytes())
+    })
+}
+
+/// Match text against glob pattern. "*" matches anything except "/",
+/// "**" matches anything and "?" matches single character except "/".
+fn glob_matches(pattern: &[u8], text: &[u8]) -> bool {
+    match pattern.first() {
+        None => text.is_empty(),
+        Some(b'*') if pattern.get(1) == Some(&b'*') => {
+            // "**/" matches any number of directories, including none
+            let rest = &pattern[2..];
+            let rest_after_slash = rest.strip_prefix(b"/");
+            (0..=text.len()).any(|skip| {
+                glob_matches(rest, &text[skip..])
+                    || rest_after_slash.is_some_and(|rest| glob_matches(rest, &text[skip..]))
+            })
+        }
+        Some(b'*') => {
+            let rest = &pattern[1..];
+            for skip in 0..=text.len() {
+                if glob_matches(rest, &text[skip..]) {
+                    return true;
+                }
+                if text.get(skip) == Some(&b'/') {
+                    break;
+                }
+            }
+            false
+        }
+        Some(b'?') => match text.first() {
+            Some(character) if *character != b'/' => glob_matches(&pattern[1..], &text[1..]),
+            _ => false,
+        },
+        Some(character) => {
+            text.first() == Some(character) && glob_matches(&pattern[1..], &text[1..])
+        }
+    }
+}
+
+/// Name of the output file, made from the name template.
+pub fn output_name(args: &Cli, input: &Path) -> String {
+    let stem = input
+        .file_stem()
+        .expect("Failed to get file stem from input file path.")
+        .to_str()
+        .unwrap();
+    let extension = if let Some(format) = &args.format {
+        output::extension_for_muxer(format)
+    } else if args.fast {
+        String::from("mpeg")
+    } else {
+        input
+            .extension()
+            .map(|extension| extension.to_string_lossy().to_string())
+            .unwrap_or_default()
+    };
+    let parent = input
+        .canonicalize()
+        .ok()
+        .and_then(|input| {
+            input
+                .parent()
+                .and_then(|parent| parent.file_name())
+                .map(|parent| parent.to_string_lossy().to_string())
+        })
+        .unwrap_or_default();
+    let name = args
+        .name_template
+        .replace("{stem}", stem)
+        .replace("{ext}", &extension)
+        .replace("{parent}", &parent);
+    // Source without extension doesn't leave a dot at the end
+    name.trim_end_matches('.').to_string()
+}
+
+fn print_table(jobs: &[Job], results: &[Result<Summary, String>]) {
+    let names: Vec<String> = jobs
+        .iter()
+        .map(|job| job.input.display().to_string())
+        .collect();
+    let name_width = names
+        .iter()
+        .map(|name| name.len())
+        .max()
+        .unwrap_or(0)
+        .max(5);
+
+    println!();
+    println!(
+        "{:<width$}{:>12}{:>12}{:>12}  status",
+        "video",
+        "duration",
+        "summarized",
+        "saved",
+        width = name_width
+    );
+    let mut total_duration = 0.0;
+    let mut total_summarized_duration = 0.0;
+    let mut failed = 0;
+    for (name, result) in names.iter().zip(results) {
+        match result {
+            Ok(summary) => {
+                println!(
+                    "{:<width$}{:>12}{:>12}{:>12}  ok",
+                    name,
+                    format_duration(summary.duration),
+                    format_duration(summary.summarized_duration),
+                    format_duration(summary.duration - summary.summarized_duration),
+                    width = name_width
+                );
+                total_duration += summary.duration;
+                total_summarized_duration += summary.summarized_duration;
+            }
+            Err(e) => {
+                println!(
+                    "{:<width$}{:>12}{:>12}{:>12}  failed: {}",
+                    name,
+                    "-",
+                    "-",
+                    "-",
+                    e.lines().next().unwrap_or_default(),
+                    width = name_width
+                );
+                failed += 1;
+            }
+        }
+    }
+    println!(
+        "{:<width$}{:>12}{:>12}{:>12}  {} ok, {} failed",
+        "total",
+        format_duration(total_duration),
+        format_duration(total_summarized_duration),
+        format_duration(total_duration - total_summarized_duration),
+        results.len() - failed,
+        failed,
+        width = name_width
+    );
+}
+
+/// Format seconds as h:mm:ss.
+fn format_duration(seconds: f32) -> String {
+    let seconds = seconds.max(0.0).round() as usize;
+    format!(
+        "{}:{:02}:{:02}",
+        seconds / 3600,
+        seconds / 60 % 60,
+        seconds % 60
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use structopt::StructOpt;
+
+    fn args(options: &[&str]) -> Cli {
+        Cli::from_iter(
+            ["video-summarizer"]
+                .iter()
+                .chain(options.iter())
+                .chain(["input"].iter()),
+        )
+    }
+
+    fn matches(pattern: &str, text: &str) -> bool {
+        glob_matches(pattern.as_bytes(), text.as_bytes())
+    }
+
+    #[test]
+    fn star_matches_within_directory() {
+        assert!(matches("*.mp4", "lecture.mp4"));
+        assert!(matches("*", ""));
+        assert!(matches("a*b*c", "abbc"));
+        assert!(!matches("*.mp4", "lecture.mkv"));
+        assert!(!matches("*.mp4", "2020/lecture.mp4"));
+        assert!(matches("**/*.mp4", "2020/01/lecture.mp4"));
+        assert!(matches("**/*.mp4", "lecture.mp4"));
+    }
+
+    #[test]
+    fn question_mark_matches_single_character() {
+        assert!(matches("part-?.mkv", "part-1.mkv"));
+        assert!(!matches("part-?.mkv", "part-10.mkv"));
+        assert!(!matches("a?b", "a/b"));
+    }
+
+    #[test]
+    fn dot_is_literal() {
+        assert!(!matches("*.mp4", "lecture-mp4"));
+        assert!(matches("lecture.new.mp4", "lecture.new.mp4"));
+        assert!(!matches("lecture.new.mp4", "lecture_new_mp4"));
+    }
+
+    #[test]
+    fn filter_by_filename_or_path() {
+        let patterns = vec![String::from("2020-*/**/*.mkv")];
+        assert!(matches_filter(&patterns, Path::new("2020-01/a/b.mkv")));
+        assert!(!matches_filter(&patterns, Path::new("2019-01/a/b.mkv")));
+        // Without patterns, media files are picked by extension
+        assert!(matches_filter(&[], Path::new("a/lecture.MP4")));
+        assert!(!matches_filter(&[], Path::new("a/notes.txt")));
+        assert!(!matches_filter(&[], Path::new("a/README")));
+    }
+
+    #[test]
+    fn output_name_from_template() {
+        assert_eq!(
+            output_name(&args(&[]), Path::new("a/lecture.mp4")),
+            "lecture.new.mp4"
+        );
+        assert_eq!(
+            output_name(&args(&["--fast"]), Path::new("lecture.mp4")),
+            "lecture.new.mpeg"
+        );
+        assert_eq!(
+            output_name(&args(&["--format", "matroska"]), Path::new("lecture.mp4")),
+            "lecture.new.mkv"
+        );
+        assert_eq!(
+            output_name(
+                &args(&["--name-template", "{ext}-{stem}-{stem}.{ext}"]),
+                Path::new("x.y.webm")
+            ),
+            "webm-x.y-x.y.webm"
+        );
+    }
+
+    #[test]
+    fn output_name_without_extension() {
+        assert_eq!(
+            output_name(&args(&[]), Path::new("dir/lecture")),
+            "lecture.new"
+        );
+        assert_eq!(
+            output_name(&args(&["--format", "mp4"]), Path::new("lecture")),
+            "lecture.new.mp4"
+        );
+    }
+
+    #[test]
+    fn jobs_skip_previous_outputs() {
+        let directory = std::env::temp_dir().join(format!(
+            "video-summarizer-batch-test-{}",
+            std::process::id()
+        ));
+        fs::create_dir_all(directory.join("sub")).unwrap();
+        for file in &["a.mp4", "a.new.mp4", "notes.txt", "sub/b.mkv"] {
+            fs::write(directory.join(file), "").unwrap();
+        }
+
+        let mut cli = args(&[]);
+        cli.input = vec![directory.clone()];
+        let jobs = collect_jobs(&cli);
+        cli.output_dir = Some(PathBuf::from("out"));
+        let jobs_to_output_dir = collect_jobs(&cli);
+        fs::remove_dir_all(&directory).unwrap();
+
+        let jobs: Vec<(PathBuf, PathBuf)> = jobs
+            .unwrap()
+            .into_iter()
+            .map(|job| (job.input, job.output))
+            .collect();
+        assert_eq!(
+            jobs,
+            vec![
+                (directory.join("a.mp4"), directory.join("a.new.mp4")),
+                (directory.join("sub/b.mkv"), directory.join("sub/b.new.mkv")),
+            ]
+        );
+        // Outputs in other directory don't collide with sources
+        let outputs: Vec<PathBuf> = jobs_to_output_dir
+            .unwrap()
+            .into_iter()
+            .map(|job| job.output)
+            .collect();
+        assert_eq!(
+            outputs,
+            vec![
+                PathBuf::from("out/a.new.mp4"),
+                PathBuf::from("out/a.new.new.mp4"),
+                PathBuf::from("out/sub/b.new.mkv"),
+            ]
+        );
+    }
+}
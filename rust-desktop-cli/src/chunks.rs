@@ -0,0 +1,323 @@
+use std::fs;
+use std::path::Path;
+use std::process::{Command, Stdio};
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use crate::encoding::EncodingSettings;
+use crate::filters::FilterSettings;
+use crate::output::{self, OutputFormat};
+use crate::parallel;
+use crate::tempdir::TempDir;
+use crate::{Cli, SpeedupRange, VideoMetadata};
+
+/// Part of the timeline that is rendered on its own. Ranges are
+/// relative to the start of the chunk.
+struct Chunk {
+    frame_from: usize,
+    frame_to: usize,
+    ranges: Vec<SpeedupRange>,
+}
+
+/// Render the video with complex filter, but split the timeline into chunks
+/// first, so ffmpeg doesn't have to deal with one huge filtergraph. Chunks are
+/// rendered independently (up to --jobs at the same time), in the same container
+/// and with the same encoding settings as the output, and then joined without
+/// re-encoding. Only if loudness is normalized, it's done over the whole audio
+/// when joining, and the audio is encoded only then.
+pub fn render_in_chunks(
+    args: &Cli,
+    input: &Path,
+    output: &Path,
+    ranges: &[SpeedupRange],
+    metadata: &VideoMetadata,
+    output_format: &OutputFormat,
+    encoding_settings: &EncodingSettings,
+) -> Result<(), String> {
+    let chunks = split_into_chunks(
+        ranges,
+        metadata,
+        args.chunk_segments,
+        args.chunk_minutes.map(|minutes| minutes * 60.0),
+    );
+    if chunks.is_empty() {
+        return Err(String::from("Nothing is left of the video to render."));
+    }
+    let jobs = args.jobs().min(chunks.len());
+    // Filters that apply to whole audio are left for the join
+    let mut filter_settings = FilterSettings::new(args);
+    let joined_audio_filters = filter_settings.joined_audio_filters();
+    filter_settings.loudnorm = false;
+
+    // Chunks rendered at the same time share the threads
+    let mut chunk_encoding_settings = encoding_settings.clone();
+    chunk_encoding_settings.threads = (encoding_settings.threads / jobs.max(1)).max(1);
+    // Chunks are files, so options for streaming to stdout are not needed
+    let chunk_format = if joined_audio_filters.is_empty() {
+        // Chunks are stored in the output container, so codecs ffmpeg picks
+        // by default are the same as for the output and can be copied into it
+        OutputFormat {
+            muxer: output_format.muxer.clone(),
+            muxer_args: Vec::new(),
+        }
+    } else {
+        // Audio is encoded only once it's filtered in the join, so it's kept
+        // lossless until then. Matroska holds it along with the video codec
+        // the output would have.
+        if chunk_encoding_settings.video_codec.is_none() {
+            chunk_encoding_settings.video_codec = output::default_video_codec(&output_format.muxer);
+        }
+        chunk_encoding_settings.audio_codec = Some(String::from("pcm_s16le"));
+        chunk_encoding_settings.audio_bitrate = None;
+        OutputFormat {
+            muxer: String::from("matroska"),
+            muxer_args: Vec::new(),
+        }
+    };
+    let chunk_extension = output::extension_for_muxer(&chunk_format.muxer);
+
+    let tempdir = TempDir::new();
+    let chunks_done = AtomicUsize::new(0);
+    let chunk_paths = parallel::map(&chunks, jobs, |index, chunk| {
+        let filter_path = tempdir.path().join(format!("chunk-{:06}.txt", index));
+        let chunk_path = tempdir
+            .path()
+            .join(format!("chunk-{:06}.{}", index, chunk_extension));
+        let filter =
+            crate::generate_complex_speedup_filter(&chunk.ranges, metadata, &filter_settings);
+        fs::write(&filter_path, filter).expect("Failed to write complex filter.");
+
+        // Read only the part of the input the chunk needs, with one more
+        // frame, so the last frame isn't lost to rounding
+        let seconds_from = chunk.frame_from as f32 / metadata.fps;
+        let seconds_length = (chunk.frame_to - chunk.frame_from + 1) as f32 / metadata.fps;
+        crate::speedup_using_complex_filter(
+            input,
+            Some((seconds_from, seconds_length)),
+            &chunk_path,
+            filter_path.to_str().unwrap(),
+            args.audio,
+            &chunk_format,
+            &chunk_encoding_settings,
+        );
+
+        let done = chunks_done.fetch_add(1, Ordering::SeqCst) + 1;
+        if !args.quiet {
+            eprintln!("Rendered {} of {} chunks", done, chunks.len());
+        }
+        chunk_path
+    });
+
+    // Join the chunks
+    let list_path = tempdir.path().join("chunks.txt");
+    fs::write(
+        &list_path,
+        chunk_paths
+            .iter()
+            .map(|path| format!("file '{}'", path.to_str().unwrap()))
+            .collect::<Vec<String>>()
+            .join("\n"),
+    )
+    .expect("Failed to write list of chunks.");
+    let join_args: Vec<String> = if joined_audio_filters.is_empty() {
+        vec![String::from("-c"), String::from("copy")]
+    } else {
+        let mut join_args = vec![
+            String::from("-c:v"),
+            String::from("copy"),
+            String::from("-af"),
+            joined_audio_filters.join(","),
+        ];
+        join_args.extend(encoding_settings.audio_args());
+        join_args.extend(vec![
+            String::from("-threads"),
+            encoding_settings.threads.to_string(),
+        ]);
+        join_args
+    };
+    let status = Command::new("ffmpeg")
+        .args([
+            "-f",
+            "concat",
+            "-safe",
+            "0",
+            "-i",
+            list_path.to_str().unwrap(),
+        ])
+        .args(join_args)
+        .args(output_format.args())
+        .arg(output.to_str().unwrap())
+        .stdin(Stdio::null())
+        .stdout(Stdio::inherit())
+        .stderr(Stdio::null())
+        .spawn()
+        .expect("Failed to run chunk concatenate process")
+        .wait()
+        .expect("Failed to concatenate chunks.");
+    if !status.success() {
+        return Err(String::from("Failed to concatenate chunks."));
+    }
+    Ok(())
+}
+
+/// Split ranges into chunks, each with at most `max_segments` segments and
+/// at most `max_seconds` of the source video. Chunk always has at least one segment.
+/// Dropped segments are left out.
+fn split_into_chunks(
+    ranges: &[SpeedupRange],
+    metadata: &VideoMetadata,
+    max_segments: Option<usize>,
+    max_seconds: Option<f32>,
+) -> Vec<Chunk> {
+    let mut chunks: Vec<Chunk> = Vec::new();
+    let mut current: Vec<&SpeedupRange> = Vec::new();
+    // Segments with speed of 100 or more are dropped
+    for range in ranges
+        .iter()
+        .filter(|range| range.frame_to > range.frame_from && range.speedup_rate < 100.0)
+    {
+        if let Some(first) = current.first() {
+            let too_many = max_segments.is_some_and(|max| current.len() >= max);
+            let too_long = max_seconds
+                .is_some_and(|max| (range.frame_to - first.frame_from) as f32 / metadata.fps > max);
+            if too_many || too_long {
+                chunks.push(make_chunk(&current));
+                current.clear();
+            }
+        }
+        current.push(range);
+    }
+    if !current.is_empty() {
+        chunks.push(make_chunk(&current));
+    }
+    chunks
+}
+
+fn make_chunk(ranges: &[&SpeedupRange]) -> Chunk {
+    let frame_from = ranges.first().unwrap().frame_from;
+    Chunk {
+        frame_from,
+        frame_to: ranges.last().unwrap().frame_to,
+        ranges: ranges
+            .iter()
+            .map(|range| {
+                SpeedupRange::new(
+                    range.frame_from - frame_from,
+                    range.frame_to - frame_from,
+                    range.speedup_rate,
+                    range.silent,
+                )
+            })
+            .collect(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn metadata() -> VideoMetadata {
+        VideoMetadata {
+            fps: 10.0,
+            duration_seconds: 10.0,
+            total_frames: 100,
+        }
+    }
+
+    /// Ranges 0-10, 10-10 (empty), 10-40, 40-45 and 45-100 frames.
+    fn ranges() -> Vec<SpeedupRange> {
+        vec![
+            SpeedupRange::new(0, 10, 1.0, false),
+            SpeedupRange::new(10, 10, 5.0, true),
+            SpeedupRange::new(10, 40, 5.0, true),
+            SpeedupRange::new(40, 45, 1.0, false),
+            SpeedupRange::new(45, 100, 5.0, true),
+        ]
+    }
+
+    /// Frames covered by a chunk or a range, from and to.
+    type Frames = (usize, usize);
+
+    /// Frames each chunk covers, and its ranges relative to the chunk.
+    fn describe(chunks: &[Chunk]) -> Vec<(Frames, Vec<Frames>)> {
+        chunks
+            .iter()
+            .map(|chunk| {
+                (
+                    (chunk.frame_from, chunk.frame_to),
+                    chunk
+                        .ranges
+                        .iter()
+                        .map(|range| (range.frame_from, range.frame_to))
+                        .collect(),
+                )
+            })
+            .collect()
+    }
+
+    #[test]
+    fn split_by_segments() {
+        let chunks = split_into_chunks(&ranges(), &metadata(), Some(2), None);
+        assert_eq!(
+            describe(&chunks),
+            vec![
+                ((0, 40), vec![(0, 10), (10, 40)]),
+                ((40, 100), vec![(0, 5), (5, 60)]),
+            ]
+        );
+    }
+
+    #[test]
+    fn split_by_length_inside_segment() {
+        // 3 seconds end inside the second segment, which goes to the next chunk
+        let chunks = split_into_chunks(&ranges(), &metadata(), None, Some(3.0));
+        assert_eq!(
+            describe(&chunks),
+            vec![
+                ((0, 10), vec![(0, 10)]),
+                ((10, 40), vec![(0, 30)]),
+                ((40, 45), vec![(0, 5)]),
+                // Segment longer than the limit is a chunk on its own
+                ((45, 100), vec![(0, 55)]),
+            ]
+        );
+    }
+
+    #[test]
+    fn split_by_both() {
+        let chunks = split_into_chunks(&ranges(), &metadata(), Some(3), Some(5.0));
+        assert_eq!(
+            describe(&chunks),
+            vec![
+                ((0, 45), vec![(0, 10), (10, 40), (40, 45)]),
+                ((45, 100), vec![(0, 55)]),
+            ]
+        );
+    }
+
+    #[test]
+    fn dropped_segments_left_out() {
+        let ranges = vec![
+            SpeedupRange::new(0, 10, 1.0, false),
+            SpeedupRange::new(10, 40, 100.0, true),
+            SpeedupRange::new(40, 45, 1.0, false),
+            SpeedupRange::new(45, 100, 150.0, true),
+        ];
+        let chunks = split_into_chunks(&ranges, &metadata(), Some(1), None);
+        assert_eq!(
+            describe(&chunks),
+            vec![((0, 10), vec![(0, 10)]), ((40, 45), vec![(0, 5)])]
+        );
+        let ranges = vec![SpeedupRange::new(0, 100, 100.0, true)];
+        assert!(split_into_chunks(&ranges, &metadata(), Some(1), None).is_empty());
+    }
+
+    #[test]
+    fn no_chunks_without_segments() {
+        let ranges = vec![SpeedupRange::new(5, 5, 1.0, false)];
+        assert!(split_into_chunks(&ranges, &metadata(), Some(2), None).is_empty());
+        assert_eq!(
+            describe(&split_into_chunks(&[], &metadata(), None, None)),
+            vec![]
+        );
+    }
+}
@@ -0,0 +1,369 @@
+use serde::Deserialize;
+use structopt::clap::AppSettings;
+use structopt::StructOpt;
+use toml::value::{Table, Value};
+
+use std::collections::HashMap;
+use std::env;
+use std::ffi::OsString;
+use std::fs;
+use std::path::PathBuf;
+
+use crate::presets::Preset;
+use crate::Cli;
+
+/// Name of the configuration file that applies to the directory it's in.
+const DIRECTORY_CONFIG_FILENAME: &str = "video-summarizer.toml";
+
+/// Settings from all configuration files.
+///
+/// Top-level keys of a configuration file set default values of command line
+/// options, and are named as the long options (`speed-silent = 6`, `quiet = true`).
+/// Profiles (`[profiles.<name>]`) contain the same keys and are selected with
+/// the --profile option, or with the `profile` key. Presets (`[presets.<name>]`)
+/// are described in the presets module.
+///
+/// Flags set in configuration are turned off on the command line
+/// with `--no-<flag>`, such as `--no-quiet`.
+#[derive(Default)]
+pub struct Config {
+    /// Encoding presets by name, from all configuration files
+    pub presets: HashMap<String, Preset>,
+    /// Parameter profiles by name, from all configuration files
+    profiles: HashMap<String, Table>,
+    /// Option defaults from each configuration file, in order of precedence,
+    /// lowest first
+    files: Vec<(PathBuf, Table)>,
+}
+
+#[derive(Deserialize)]
+struct ConfigFile {
+    #[serde(default)]
+    presets: HashMap<String, Preset>,
+    #[serde(default)]
+    profiles: HashMap<String, Table>,
+    #[serde(flatten)]
+    options: Table,
+}
+
+/// Where configuration files are looked for, lowest precedence first:
+/// system-wide, user's, and the one in current directory.
+///
+/// System-wide file is `/etc/video-summarizer/config.toml` (`%PROGRAMDATA%`
+/// on Windows). User's file is `video-summarizer/config.toml` in
+/// `$XDG_CONFIG_HOME` (`~/.config` if not set), or in `%APPDATA%` on Windows.
+/// The file in current directory is `video-summarizer.toml`.
+pub fn config_paths() -> Vec<PathBuf> {
+    let system_directory = if cfg!(windows) {
+        env::var_os("PROGRAMDATA").map(PathBuf::from)
+    } else {
+        Some(PathBuf::from("/etc"))
+    };
+    let user_directory = if cfg!(windows) {
+        env::var_os("APPDATA").map(PathBuf::from)
+    } else {
+        env::var_os("XDG_CONFIG_HOME")
+            .filter(|directory| !directory.is_empty())
+            .map(PathBuf::from)
+            .or_else(|| env::var_os("HOME").map(|home| PathBuf::from(home).join(".config")))
+    };
+
+    let mut paths: Vec<PathBuf> = vec![system_directory, user_directory]
+        .into_iter()
+        .flatten()
+        .map(|directory| directory.join("video-summarizer").join("config.toml"))
+        .collect();
+    paths.push(PathBuf::from(DIRECTORY_CONFIG_FILENAME));
+    paths
+}
+
+/// Read all configuration files that exist. Presets and profiles
+/// with the same name are taken from the file with higher precedence.
+pub fn load() -> Result<Config, String> {
+    let mut config = Config::default();
+    for path in config_paths() {
+        if !path.exists() {
+            continue;
+        }
+        let contents = fs::read_to_string(&path)
+            .map_err(|e| format!("Failed to read config file {}: {}", path.display(), e))?;
+        let file: ConfigFile = toml::from_str(&contents)
+            .map_err(|e| format!("Failed to parse config file {}: {}", path.display(), e))?;
+        config.presets.extend(file.presets);
+        config.profiles.extend(file.profiles);
+        config.files.push((path, file.options));
+    }
+    Ok(config)
+}
+
+impl Config {
+    /// Parse command line arguments. Options that are not set on the command
+    /// line are taken from the selected profile, then from the configuration
+    /// files, and then the built-in defaults are used.
+    ///
+    /// If the command line itself is invalid (or help is requested), this
+    /// exits the same way as `Cli::from_args`.
+    pub fn parse_args(&self) -> Result<Cli, String> {
+        self.parse_args_from(env::args_os().collect())
+    }
+
+    fn parse_args_from(&self, command_line: Vec<OsString>) -> Result<Cli, String> {
+        // Flags from configuration that are turned off with --no-<flag>
+        let flags = self.flags();
+        let (negated, command_line): (Vec<OsString>, Vec<OsString>) =
+            command_line.into_iter().partition(|arg| {
+                arg.to_str()
+                    .and_then(|arg| arg.strip_prefix("--no-"))
+                    .is_some_and(|flag| flags.contains(&flag))
+            });
+        let negated: Vec<OsString> = negated
+            .iter()
+            .map(|arg| OsString::from(arg.to_string_lossy().replacen("--no-", "--", 1)))
+            .collect();
+
+        let command_line_matches = Cli::clap().get_matches_from(&command_line);
+
+        // Profile can be selected on command line, or in configuration file
+        let mut profile_name = command_line_matches.value_of("profile").map(String::from);
+        if profile_name.is_none() {
+            for (path, options) in self.files.iter().rev() {
+                if let Some(profile) = options.get("profile") {
+                    profile_name = Some(
+                        profile
+                            .as_str()
+                            .ok_or_else(|| {
+                                format!("Profile name in {} is not a string.", path.display())
+                            })?
+                            .to_string(),
+                    );
+                    break;
+                }
+            }
+        }
+
+        let mut sources: Vec<(String, &Table)> = self
+            .files
+            .iter()
+            .map(|(path, options)| (path.display().to_string(), options))
+            .collect();
+        if let Some(profile_name) = profile_name {
+            let profile = self.profiles.get(&profile_name).ok_or_else(|| {
+                let mut names: Vec<&str> = self.profiles.keys().map(|n| n.as_str()).collect();
+                names.sort();
+                format!(
+                    "Unknown profile \"{}\". Available profiles are: {}.",
+                    profile_name,
+                    names.join(", ")
+                )
+            })?;
+            sources.push((format!("profile \"{}\"", profile_name), profile));
+        }
+
+        // Put options from configuration before the command line, lowest
+        // precedence first. When an option is repeated, the last one is used.
+        let mut args: Vec<OsString> = vec![command_line[0].clone()];
+        for (source, options) in sources {
+            let source_args = options_to_args(options)
+                .map_err(|e| format!("Invalid option in {}: {}", source, e))?;
+            // Check the options on their own, so errors point to where they came from
+            let mut check_args = vec![command_line[0].clone()];
+            check_args.extend(source_args.iter().cloned());
+            check_args.push(OsString::from("input"));
+            Cli::clap()
+                .setting(AppSettings::ColorNever)
+                .get_matches_from_safe(check_args)
+                .map_err(|e| {
+                    // Only the first line says what's wrong, the rest is usage
+                    let reason = e.message.lines().next().unwrap_or_default();
+                    let reason = reason.trim_start_matches("error: ");
+                    format!("Invalid option in {}: {}", source, reason)
+                })?;
+
+            if sets_quality(&source_args) {
+                remove_quality_args(&mut args);
+            }
+            args.extend(source_args);
+        }
+        if command_line_matches.is_present("crf") || command_line_matches.is_present("bitrate") {
+            remove_quality_args(&mut args);
+        }
+        args.retain(|arg| !negated.contains(arg));
+        // Options that take multiple values would be added to the configured
+        // ones, so options set on the command line replace them instead
+        let command_line_options: Vec<String> = command_line
+            .iter()
+            .skip(1)
+            .filter_map(option_name)
+            .collect();
+        args.retain(|arg| {
+            !option_name(arg).is_some_and(|name| command_line_options.contains(&name))
+        });
+        args.extend(command_line.into_iter().skip(1));
+
+        let matches = Cli::clap()
+            .setting(AppSettings::AllArgsOverrideSelf)
+            .get_matches_from_safe(args)
+            .map_err(|e| e.message)?;
+        Ok(Cli::from_clap(&matches))
+    }
+
+    /// Names of flags that are set in any configuration file or profile.
+    fn flags(&self) -> Vec<&str> {
+        self.files
+            .iter()
+            .map(|(_, options)| options)
+            .chain(self.profiles.values())
+            .flat_map(|options| options.iter())
+            .filter(|(_, value)| **value == Value::Boolean(true))
+            .map(|(key, _)| key.as_str())
+            .collect()
+    }
+}
+
+/// Translate options from configuration to command line arguments.
+/// Flags are set with `true`, and options that take multiple values with arrays.
+fn options_to_args(options: &Table) -> Result<Vec<OsString>, String> {
+    let mut args: Vec<OsString> = Vec::new();
+    for (key, value) in options {
+        let values = match value {
+            Value::Array(values) => values.iter().collect(),
+            value => vec![value],
+        };
+        for value in values {
+            let value = match value {
+                Value::Boolean(true) => None,
+                Value::Boolean(false) => continue,
+                Value::String(value) => Some(value.clone()),
+                Value::Integer(value) => Some(value.to_string()),
+                Value::Float(value) => Some(value.to_string()),
+                _ => return Err(format!("unsupported value of \"{}\"", key)),
+            };
+            args.push(OsString::from(match value {
+                Some(value) => format!("--{}={}", key, value),
+                None => format!("--{}", key),
+            }));
+        }
+    }
+    Ok(args)
+}
+
+/// Name of long option, such as "glob" for "--glob=*.mp4".
+fn option_name(arg: &OsString) -> Option<String> {
+    let arg = arg.to_str()?.strip_prefix("--")?;
+    Some(arg.split('=').next().unwrap_or_default().to_string())
+}
+
+/// CRF and bitrate cannot be used together, so setting one of them
+/// replaces both from configuration with lower precedence.
+fn is_quality_arg(arg: &OsString) -> bool {
+    let arg = arg.to_string_lossy();
+    arg.starts_with("--crf=") || arg.starts_with("--bitrate=")
+}
+
+/// Remove CRF and bitrate, both as `--crf=20` and as `--crf 20`.
+fn remove_quality_args(args: &mut Vec<OsString>) {
+    let mut iter = std::mem::take(args).into_iter();
+    while let Some(arg) = iter.next() {
+        if arg == "--crf" || arg == "--bitrate" {
+            iter.next();
+        } else if !is_quality_arg(&arg) {
+            args.push(arg);
+        }
+    }
+}
+
+fn sets_quality(args: &[OsString]) -> bool {
+    args.iter().any(is_quality_arg)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn table(contents: &str) -> Table {
+        toml::from_str(contents).unwrap()
+    }
+
+    fn configured(system: &str, user: &str, profile: &str) -> Config {
+        let mut config = Config::default();
+        config
+            .files
+            .push((PathBuf::from("system.toml"), table(system)));
+        config.files.push((PathBuf::from("user.toml"), table(user)));
+        config
+            .profiles
+            .insert(String::from("lectures"), table(profile));
+        config
+    }
+
+    fn parse(config: &Config, command_line: &[&str]) -> Cli {
+        let mut args = vec![OsString::from("video-summarizer")];
+        args.extend(command_line.iter().map(OsString::from));
+        args.push(OsString::from("input.mp4"));
+        config.parse_args_from(args).unwrap()
+    }
+
+    #[test]
+    fn precedence() {
+        let config = configured("speed-silent = 2", "speed-silent = 3", "speed-silent = 4");
+        assert_eq!(parse(&Config::default(), &[]).speed_silent, 5.0);
+        assert_eq!(parse(&config, &[]).speed_silent, 3.0);
+        assert_eq!(parse(&config, &["--profile", "lectures"]).speed_silent, 4.0);
+        assert_eq!(
+            parse(&config, &["--profile", "lectures", "-s", "6"]).speed_silent,
+            6.0
+        );
+
+        let config = configured("speed-silent = 2", "", "");
+        assert_eq!(parse(&config, &[]).speed_silent, 2.0);
+    }
+
+    #[test]
+    fn profile_selected_in_file() {
+        let config = configured("", "profile = \"lectures\"", "speed-loud = 2");
+        assert_eq!(parse(&config, &[]).speed_loud, 2.0);
+    }
+
+    #[test]
+    fn quality_replaced_by_command_line() {
+        let config = configured("crf = 20", "", "bitrate = \"2M\"");
+        assert_eq!(parse(&config, &[]).crf, Some(20));
+
+        let args = parse(&config, &["--profile", "lectures"]);
+        assert_eq!((args.crf, args.bitrate), (None, Some(String::from("2M"))));
+
+        let args = parse(&config, &["--profile", "lectures", "--crf", "25"]);
+        assert_eq!((args.crf, args.bitrate), (Some(25), None));
+        let args = parse(&config, &["--bitrate=1M"]);
+        assert_eq!((args.crf, args.bitrate), (None, Some(String::from("1M"))));
+    }
+
+    #[test]
+    fn remove_quality_in_both_forms() {
+        let mut args: Vec<OsString> = ["-s", "4", "--crf", "20", "--bitrate=1M", "-q"]
+            .iter()
+            .map(OsString::from)
+            .collect();
+        remove_quality_args(&mut args);
+        assert_eq!(args, vec!["-s", "4", "-q"]);
+    }
+
+    #[test]
+    fn multiple_values_replaced_by_command_line() {
+        let config = configured("glob = [\"*.mp4\", \"*.mkv\"]", "", "");
+        assert_eq!(parse(&config, &[]).glob, vec!["*.mp4", "*.mkv"]);
+        assert_eq!(parse(&config, &["--glob", "*.webm"]).glob, vec!["*.webm"]);
+        assert_eq!(
+            parse(&config, &["--glob=*.webm", "--glob=*.ts"]).glob,
+            vec!["*.webm", "*.ts"]
+        );
+    }
+
+    #[test]
+    fn flags_turned_off_on_command_line() {
+        let config = configured("quiet = true", "", "");
+        assert!(parse(&config, &[]).quiet);
+        assert!(!parse(&config, &["--no-quiet"]).quiet);
+        assert!(!parse(&Config::default(), &[]).quiet);
+    }
+}
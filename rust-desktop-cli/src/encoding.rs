@@ -0,0 +1,136 @@
+use std::process::{Command, Stdio};
+
+use crate::output::OutputFormat;
+use crate::tempdir::TempDir;
+
+/// Default constant rate factor, used when neither CRF nor bitrate is set.
+const DEFAULT_CRF: &str = "27";
+/// Default encoder preset, used when user didn't choose one.
+const DEFAULT_ENCODER_PRESET: &str = "faster";
+
+/// How the output is encoded. Codecs that are not set
+/// are chosen by ffmpeg, depending on the container.
+#[derive(Clone)]
+pub struct EncodingSettings {
+    pub video_codec: Option<String>,
+    pub audio_codec: Option<String>,
+    pub crf: Option<u8>,
+    pub bitrate: Option<String>,
+    pub audio_bitrate: Option<String>,
+    pub encoder_preset: Option<String>,
+    pub threads: usize,
+}
+
+impl EncodingSettings {
+    /// ffmpeg output arguments that select codecs and quality.
+    pub fn args(&self, audio_only: bool) -> Vec<String> {
+        let mut args: Vec<String> = Vec::new();
+        if audio_only {
+            args.push(String::from("-vn"));
+        } else {
+            if let Some(video_codec) = &self.video_codec {
+                args.extend(vec![String::from("-c:v"), video_codec.clone()]);
+            }
+            match (&self.bitrate, self.crf) {
+                (Some(bitrate), _) => args.extend(vec![String::from("-b:v"), bitrate.clone()]),
+                (None, Some(crf)) => args.extend(vec![String::from("-crf"), crf.to_string()]),
+                (None, None) => args.extend(vec![String::from("-crf"), String::from(DEFAULT_CRF)]),
+            }
+            let encoder_preset = self
+                .encoder_preset
+                .as_deref()
+                .unwrap_or(DEFAULT_ENCODER_PRESET);
+            args.extend(vec![String::from("-preset"), encoder_preset.to_string()]);
+        }
+        args.extend(self.audio_args());
+        args.extend(vec![String::from("-threads"), self.threads.to_string()]);
+        args
+    }
+
+    /// ffmpeg output arguments that select audio codec and quality.
+    pub fn audio_args(&self) -> Vec<String> {
+        let mut args: Vec<String> = Vec::new();
+        if let Some(audio_codec) = &self.audio_codec {
+            args.extend(vec![String::from("-c:a"), audio_codec.clone()]);
+        }
+        if let Some(audio_bitrate) = &self.audio_bitrate {
+            args.extend(vec![String::from("-b:a"), audio_bitrate.clone()]);
+        }
+        args
+    }
+
+    /// Make sure ffmpeg can encode the chosen codecs and put them into the
+    /// chosen container, so we don't find out after hours of encoding.
+    ///
+    /// This encodes a fraction of a second of generated video and audio
+    /// with the same settings as the real output.
+    pub fn validate(&self, output_format: &OutputFormat, audio_only: bool) -> Result<(), String> {
+        let tempdir = TempDir::new();
+        let test_output = tempdir.path().join("validate");
+        let mut args: Vec<String> = Vec::new();
+        if !audio_only {
+            args.extend(
+                [
+                    "-f",
+                    "lavfi",
+                    "-i",
+                    "testsrc=size=320x240:rate=25:duration=0.2",
+                ]
+                .iter()
+                .map(|arg| arg.to_string()),
+            );
+        }
+        args.extend(
+            [
+                "-f",
+                "lavfi",
+                "-i",
+                "anullsrc=sample_rate=44100",
+                "-t",
+                "0.2",
+            ]
+            .iter()
+            .map(|arg| arg.to_string()),
+        );
+        args.extend(self.args(audio_only));
+        args.extend(output_format.args().iter().map(|arg| arg.to_string()));
+        args.push(String::from("-y"));
+        args.push(test_output.to_str().unwrap().to_string());
+
+        let validation = Command::new("ffmpeg")
+            .args(&args)
+            .stdin(Stdio::null())
+            .stdout(Stdio::null())
+            .stderr(Stdio::piped())
+            .output()
+            .expect("Failed to spawn ffmpeg to validate encoding settings.");
+        if validation.status.success() {
+            return Ok(());
+        }
+
+        // Last line ffmpeg printed usually says what's wrong
+        let stderr = String::from_utf8_lossy(&validation.stderr);
+        let reason = stderr
+            .lines()
+            .rev()
+            .find(|line| !line.trim().is_empty())
+            .unwrap_or("unknown error");
+        Err(format!(
+            "Cannot encode {} with {} in container \"{}\": {}",
+            if audio_only { "audio" } else { "video" },
+            self.describe_codecs(audio_only),
+            output_format.muxer,
+            reason.trim()
+        ))
+    }
+
+    fn describe_codecs(&self, audio_only: bool) -> String {
+        let video_codec = self.video_codec.as_deref().unwrap_or("default video codec");
+        let audio_codec = self.audio_codec.as_deref().unwrap_or("default audio codec");
+        if audio_only {
+            String::from(audio_codec)
+        } else {
+            format!("{} and {}", video_codec, audio_codec)
+        }
+    }
+}
@@ -0,0 +1,601 @@
+use std::process::{Command, Stdio};
+use std::str::FromStr;
+use std::sync::OnceLock;
+
+use crate::overlay::SpeedOverlay;
+use crate::Cli;
+
+/// Highest tempo a single atempo filter is asked for. Newer ffmpeg
+/// accepts more, but it sounds worse, so higher speeds are chained.
+const MAX_STAGE_TEMPO: f32 = 2.0;
+/// Lowest tempo a single atempo filter is asked for.
+const MIN_STAGE_TEMPO: f32 = 0.5;
+/// Speeds that need more atempo filters than this are rejected.
+const MAX_STAGES: usize = 16;
+/// Lowest tempo the rubberband filter accepts. Highest one is 100, and
+/// segments that fast are left out of the render, so it's never asked for.
+const RUBBERBAND_MIN_TEMPO: f32 = 0.01;
+
+/// Tempo range of single atempo filter of installed ffmpeg, probed once.
+/// `None` if ffmpeg doesn't have the filter.
+static ATEMPO_RANGE: OnceLock<Option<(f32, f32)>> = OnceLock::new();
+/// Names of filters installed ffmpeg has, probed once.
+static AVAILABLE_FILTERS: OnceLock<Vec<String>> = OnceLock::new();
+/// Sample rate audio is resampled to when its speed is changed by resampling.
+const RESAMPLE_RATE: u32 = 48000;
+
+/// How audio of neighbouring segments is joined.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum AudioJoin {
+    /// Hard cut, which can be heard as a click.
+    Cut,
+    /// Every segment fades in and out.
+    Fade,
+    /// Audio of every segment overlaps with the next one.
+    Crossfade,
+}
+impl FromStr for AudioJoin {
+    type Err = String;
+
+    fn from_str(join: &str) -> Result<AudioJoin, String> {
+        match join {
+            "cut" => Ok(AudioJoin::Cut),
+            "fade" => Ok(AudioJoin::Fade),
+            "crossfade" => Ok(AudioJoin::Crossfade),
+            _ => Err(format!("Unknown audio join: {}", join)),
+        }
+    }
+}
+
+/// How speed of audio is changed.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Stretch {
+    /// ffmpeg's atempo filter, which keeps pitch.
+    Atempo,
+    /// Rubber Band library, which keeps pitch and sounds better with music.
+    /// Only some ffmpeg builds have it.
+    Rubberband,
+    /// Play the samples faster, which raises pitch. This is the cheapest.
+    Resample,
+}
+impl FromStr for Stretch {
+    type Err = String;
+
+    fn from_str(stretch: &str) -> Result<Stretch, String> {
+        match stretch {
+            "atempo" => Ok(Stretch::Atempo),
+            "rubberband" => Ok(Stretch::Rubberband),
+            "resample" => Ok(Stretch::Resample),
+            _ => Err(format!("Unknown stretch method: {}", stretch)),
+        }
+    }
+}
+
+/// How frames are made when the output frame rate is set.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum FpsMode {
+    /// Frames are dropped or duplicated.
+    Drop,
+    /// Neighbouring frames are blended.
+    Blend,
+}
+impl FromStr for FpsMode {
+    type Err = String;
+
+    fn from_str(mode: &str) -> Result<FpsMode, String> {
+        match mode {
+            "drop" => Ok(FpsMode::Drop),
+            "blend" => Ok(FpsMode::Blend),
+            _ => Err(format!("Unknown frame rate mode: {}", mode)),
+        }
+    }
+}
+
+/// How many frames are blended together in fast segments. More
+/// frames look smoother, but take longer to render.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum BlendQuality {
+    Low,
+    Medium,
+    High,
+}
+impl BlendQuality {
+    /// Most frames blended together into one.
+    fn max_frames(self) -> u32 {
+        match self {
+            BlendQuality::Low => 4,
+            BlendQuality::Medium => 8,
+            BlendQuality::High => 16,
+        }
+    }
+}
+impl FromStr for BlendQuality {
+    type Err = String;
+
+    fn from_str(quality: &str) -> Result<BlendQuality, String> {
+        match quality {
+            "low" => Ok(BlendQuality::Low),
+            "medium" => Ok(BlendQuality::Medium),
+            "high" => Ok(BlendQuality::High),
+            _ => Err(format!("Unknown motion blend quality: {}", quality)),
+        }
+    }
+}
+
+/// How strongly video is denoised.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Denoise {
+    Light,
+    Medium,
+    Strong,
+}
+impl FromStr for Denoise {
+    type Err = String;
+
+    fn from_str(denoise: &str) -> Result<Denoise, String> {
+        match denoise {
+            "light" => Ok(Denoise::Light),
+            "medium" => Ok(Denoise::Medium),
+            "strong" => Ok(Denoise::Strong),
+            _ => Err(format!("Unknown denoise strength: {}", denoise)),
+        }
+    }
+}
+
+/// What happens with audio of silent segments.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum SilentAudio {
+    Keep,
+    Mute,
+    /// Make it quieter by this many decibels.
+    Attenuate(f32),
+    /// Replace it with quiet noise, so it doesn't sound like the audio dropped out.
+    Noise,
+}
+impl FromStr for SilentAudio {
+    type Err = String;
+
+    fn from_str(silent_audio: &str) -> Result<SilentAudio, String> {
+        match silent_audio {
+            "keep" => Ok(SilentAudio::Keep),
+            "mute" => Ok(SilentAudio::Mute),
+            "noise" => Ok(SilentAudio::Noise),
+            _ => match silent_audio.trim_end_matches("dB").parse::<f32>() {
+                Ok(decibels) if decibels.is_finite() => Ok(SilentAudio::Attenuate(decibels.abs())),
+                _ => Err(format!("Unknown silent audio: {}", silent_audio)),
+            },
+        }
+    }
+}
+
+/// Settings of the filters applied to every segment, both
+/// with complex filter and in fast mode.
+pub struct FilterSettings {
+    pub audio_only: bool,
+    pub max_height: Option<u32>,
+    pub audio_join: AudioJoin,
+    /// Length of fades or crossfades, in seconds.
+    pub join_duration: f32,
+    pub stretch_loud: Stretch,
+    pub stretch_silent: Stretch,
+    pub silent_audio: SilentAudio,
+    pub overlay: Option<SpeedOverlay>,
+    pub output_fps: Option<f32>,
+    pub fps_mode: FpsMode,
+    /// Segments at least this fast have their frames blended.
+    pub motion_blend_speed: Option<f32>,
+    pub motion_blend_quality: BlendQuality,
+    pub denoise: Option<Denoise>,
+    /// Frequency under which audio is cut off, in Hz.
+    pub highpass: Option<u32>,
+    pub loudnorm: bool,
+}
+impl FilterSettings {
+    pub fn new(args: &Cli) -> FilterSettings {
+        FilterSettings {
+            audio_only: args.audio,
+            max_height: args.max_height,
+            audio_join: args.audio_join,
+            join_duration: args.audio_join_ms as f32 / 1000.0,
+            stretch_loud: args.stretch_loud,
+            stretch_silent: args.stretch_silent,
+            silent_audio: args.silent_audio,
+            overlay: SpeedOverlay::new(args),
+            output_fps: args.output_fps,
+            fps_mode: args.fps_mode,
+            motion_blend_speed: args.motion_blend_speed,
+            motion_blend_quality: args.motion_blend_quality,
+            denoise: args.denoise,
+            highpass: args.highpass,
+            loudnorm: args.loudnorm,
+        }
+    }
+
+    /// Settings that change parts made in fast mode, so parts made
+    /// with other settings are not reused.
+    pub fn parameters(&self) -> Vec<String> {
+        vec![
+            format!("{:?}", self.audio_join),
+            self.join_duration.to_string(),
+            format!("{:?}", self.stretch_loud),
+            format!("{:?}", self.stretch_silent),
+            format!("{:?}", self.silent_audio),
+            format!("{:?}", self.overlay),
+            format!("{:?}", self.output_fps),
+            format!("{:?}", self.fps_mode),
+            format!("{:?}", self.motion_blend_speed),
+            format!("{:?}", self.motion_blend_quality),
+            format!("{:?}", self.denoise),
+            format!("{:?}", self.highpass),
+        ]
+    }
+
+    /// Check, before the video is analyzed, that audio can be played
+    /// at given speeds with chosen stretch methods, and that other
+    /// settings make sense.
+    pub fn validate(&self, speed_loud: f32, speed_silent: f32) -> Result<(), String> {
+        if let Some(fps) = self.output_fps {
+            if !fps.is_finite() || fps <= 0.0 {
+                return Err(format!(
+                    "Output frame rate {} is not possible, it must be larger than 0.",
+                    fps
+                ));
+            }
+        }
+        validate_stretch(self.stretch_loud, speed_loud)?;
+        validate_stretch(self.stretch_silent, speed_silent)
+    }
+
+    /// Filters applied to video of every segment, after its speed is changed.
+    /// The segment is `duration` seconds long after it's sped up.
+    pub fn video_chain(&self, speed: f32, duration: f32) -> String {
+        let mut chain = String::new();
+        // Parameters of hqdn3d: spatial luma and chroma, temporal luma and chroma
+        match self.denoise {
+            Some(Denoise::Light) => chain.push_str(",hqdn3d=2:1.5:3:2.25"),
+            Some(Denoise::Medium) => chain.push_str(",hqdn3d=4:3:6:4.5"),
+            Some(Denoise::Strong) => chain.push_str(",hqdn3d=8:6:12:9"),
+            None => {}
+        }
+        // Every frame is blended with the ones that are skipped over
+        // at this speed, so the segment looks like a time-lapse
+        if self
+            .motion_blend_speed
+            .is_some_and(|blend_speed| speed >= blend_speed && speed < 100.0)
+        {
+            let frames = (speed.round() as u32).clamp(2, self.motion_blend_quality.max_frames());
+            chain.push_str(&format!(",tmix=frames={}", frames));
+        }
+        // Overlay is drawn last, so it's not blended
+        if let Some(overlay) = &self.overlay {
+            chain.push_str(&overlay.filters(speed, duration));
+        }
+        chain
+    }
+
+    /// Filters applied to video once the segments are joined. Frame rate
+    /// is changed only then, so rounding segments to whole frames doesn't
+    /// make the video drift from audio. In fast mode, they are applied to
+    /// every part, before it's encoded.
+    pub fn joined_video_filters(&self) -> Vec<String> {
+        let mut filters: Vec<String> = Vec::new();
+        if let Some(fps) = self.output_fps {
+            filters.push(match self.fps_mode {
+                FpsMode::Drop => format!("fps={}", fps),
+                FpsMode::Blend => format!("framerate=fps={}", fps),
+            });
+        }
+        filters
+    }
+
+    /// Filters applied to audio once the segments are joined. Loudness is
+    /// normalized over the whole audio, so silent segments aren't made loud.
+    pub fn joined_audio_filters(&self) -> Vec<String> {
+        let mut filters: Vec<String> = Vec::new();
+        if self.loudnorm {
+            // loudnorm outputs 192 kHz audio
+            filters.push(String::from("loudnorm=I=-16:TP=-1.5:LRA=11"));
+            filters.push(format!("aresample={}", RESAMPLE_RATE));
+        }
+        filters
+    }
+
+    /// Filters applied to audio of every segment: high-pass filter,
+    /// change of its speed, and for silent segments, what
+    /// `--silent-audio` asks for.
+    pub fn audio_chain(&self, speed: f32, silent: bool) -> String {
+        // Cut off low frequencies before they are changed by the stretch
+        let mut chain = match self.highpass {
+            Some(frequency) => format!("highpass=f={},", frequency),
+            None => String::new(),
+        };
+        chain.push_str(&self.stretch_chain(speed, silent));
+        if silent {
+            match self.silent_audio {
+                SilentAudio::Keep => {}
+                SilentAudio::Mute => chain.push_str(",volume=0"),
+                SilentAudio::Attenuate(decibels) => {
+                    chain.push_str(&format!(",volume=-{}dB", decibels))
+                }
+                // White noise at about -54 dB, in every channel
+                SilentAudio::Noise => chain.push_str(",aeval=exprs=0.002*(random(0)*2-1):c=same"),
+            }
+        }
+        chain
+    }
+
+    /// Filters that change speed of silent or loud segment's audio.
+    fn stretch_chain(&self, speed: f32, silent: bool) -> String {
+        let stretch = if silent {
+            self.stretch_silent
+        } else {
+            self.stretch_loud
+        };
+        match stretch {
+            Stretch::Atempo => atempo_chain(speed),
+            Stretch::Rubberband => format!("rubberband=tempo={}", speed),
+            // Resample first, so we know what the sample rate is
+            Stretch::Resample => format!(
+                "aresample={},asetrate={},aresample={}",
+                RESAMPLE_RATE,
+                (RESAMPLE_RATE as f32 * speed).round() as u32,
+                RESAMPLE_RATE
+            ),
+        }
+    }
+
+    /// How much longer audio of a segment must be, so it can overlap
+    /// with the next one. Segments are cut this much later (in output time),
+    /// or less if either of them is shorter, and the overlap is removed
+    /// again by the crossfade.
+    pub fn audio_overlap(&self) -> f32 {
+        match self.audio_join {
+            AudioJoin::Crossfade => self.join_duration,
+            _ => 0.0,
+        }
+    }
+
+    /// Filters that fade audio of a segment, which is `duration` seconds long
+    /// after it's sped up, in and out. Fades are never longer than half of
+    /// the segment, and the segment stays just as long.
+    ///
+    /// This is empty unless the audio is joined with fades. Crossfades are
+    /// used in fast mode as well, since its parts can't overlap.
+    pub fn audio_fades(&self, duration: f32, crossfade_possible: bool) -> String {
+        if self.audio_join == AudioJoin::Cut
+            || (self.audio_join == AudioJoin::Crossfade && crossfade_possible)
+        {
+            return String::new();
+        }
+        let fade = self.join_duration.min(duration / 2.0);
+        format!(
+            ",afade=t=in:d={},afade=t=out:st={}:d={}",
+            fade,
+            duration - fade,
+            fade
+        )
+    }
+}
+
+/// Check that audio can be played at given speed with given stretch
+/// method. Speeds higher than or equal to 100 are dropped, so they are fine.
+fn validate_stretch(stretch: Stretch, speed: f32) -> Result<(), String> {
+    if !speed.is_finite() || speed <= 0.0 {
+        return Err(format!(
+            "Speed {} is not possible, it must be larger than 0.",
+            speed
+        ));
+    }
+    // Segments this fast are left out of every render, so their
+    // audio is never stretched
+    if speed >= 100.0 {
+        return Ok(());
+    }
+    match stretch {
+        Stretch::Atempo => validate_atempo(speed),
+        Stretch::Rubberband if speed < RUBBERBAND_MIN_TEMPO => Err(format!(
+            "Speed {} is too low for rubberband, it must be at least {}.",
+            speed, RUBBERBAND_MIN_TEMPO
+        )),
+        Stretch::Rubberband if !has_filter("rubberband") => Err(String::from(
+            "Installed ffmpeg doesn't have the rubberband filter. Use another --stretch-loud or --stretch-silent method.",
+        )),
+        Stretch::Rubberband | Stretch::Resample => Ok(()),
+    }
+}
+
+/// Check that atempo filters can play audio at given speed.
+fn validate_atempo(speed: f32) -> Result<(), String> {
+    if speed == 1.0 {
+        return Ok(());
+    }
+    let (min, max) = stage_range().ok_or_else(|| {
+        String::from(
+            "Installed ffmpeg doesn't have the atempo filter, which is needed to change speed of audio.",
+        )
+    })?;
+    validate_atempo_stages(speed, min, max)
+}
+
+/// Check that atempo filters, each accepting tempo from `min` to `max`,
+/// can play audio at given speed.
+fn validate_atempo_stages(speed: f32, min: f32, max: f32) -> Result<(), String> {
+    if min >= 1.0 || max <= 1.0 {
+        return Err(format!(
+            "Installed ffmpeg can play audio only at tempo from {} to {}, so it can't change its speed.",
+            min, max
+        ));
+    }
+    if atempo_stages(speed, min, max).len() > MAX_STAGES {
+        return Err(format!(
+            "Speed {} is too low, it must be at least {}.",
+            speed,
+            min.powi(MAX_STAGES as i32)
+        ));
+    }
+    Ok(())
+}
+
+/// Chain of atempo filters that change speed of audio, each of them with
+/// tempo that installed ffmpeg accepts, for example "atempo=2,atempo=1.25"
+/// for speed 2.5.
+fn atempo_chain(speed: f32) -> String {
+    let (min, max) = stage_range().unwrap_or((MIN_STAGE_TEMPO, MAX_STAGE_TEMPO));
+    atempo_stages(speed, min, max)
+        .iter()
+        .map(|tempo| format!("atempo={}", tempo))
+        .collect::<Vec<String>>()
+        .join(",")
+}
+
+/// Split speed into tempos between `min` and `max`, which multiply to it.
+fn atempo_stages(speed: f32, min: f32, max: f32) -> Vec<f32> {
+    let mut stages: Vec<f32> = Vec::new();
+    let mut remaining = speed;
+    while remaining > max && stages.len() <= MAX_STAGES {
+        stages.push(max);
+        remaining /= max;
+    }
+    while remaining < min && stages.len() <= MAX_STAGES {
+        stages.push(min);
+        remaining /= min;
+    }
+    stages.push(remaining);
+    stages
+}
+
+/// Tempo range used for single atempo filter.
+fn stage_range() -> Option<(f32, f32)> {
+    ATEMPO_RANGE
+        .get_or_init(probe_atempo_range)
+        .map(|(min, max)| (min.max(MIN_STAGE_TEMPO), max.min(MAX_STAGE_TEMPO)))
+}
+
+/// Whether installed ffmpeg has filter with given name.
+fn has_filter(name: &str) -> bool {
+    AVAILABLE_FILTERS
+        .get_or_init(probe_filters)
+        .iter()
+        .any(|filter| filter == name)
+}
+
+fn probe_filters() -> Vec<String> {
+    let filters = Command::new("ffmpeg")
+        .args(["-hide_banner", "-filters"])
+        .stdin(Stdio::null())
+        .stderr(Stdio::null())
+        .stdout(Stdio::piped())
+        .output()
+        .expect("Failed to run ffmpeg. Is it installed?");
+    // Expected format:
+    //  ... rubberband        A->A       Apply time-stretching and pitch-shifting.
+    String::from_utf8_lossy(&filters.stdout)
+        .lines()
+        .filter_map(|line| line.split_whitespace().nth(1))
+        .map(|name| name.to_string())
+        .collect()
+}
+
+/// Ask ffmpeg what tempo the atempo filter accepts.
+fn probe_atempo_range() -> Option<(f32, f32)> {
+    let help = Command::new("ffmpeg")
+        .args(["-hide_banner", "-h", "filter=atempo"])
+        .stdin(Stdio::null())
+        .stderr(Stdio::null())
+        .stdout(Stdio::piped())
+        .output()
+        .expect("Failed to run ffmpeg. Is it installed?");
+    parse_atempo_range(&String::from_utf8_lossy(&help.stdout))
+}
+
+/// Read tempo range from help of the atempo filter.
+fn parse_atempo_range(help: &str) -> Option<(f32, f32)> {
+    if !help.contains("atempo") || help.contains("Unknown filter") {
+        return None;
+    }
+    // Expected format:
+    //    tempo   <double>   ..F.A...... set tempo scale factor (from 0.5 to 100) (default 1)
+    help.lines()
+        .find(|line| line.trim_start().starts_with("tempo"))
+        .and_then(|line| {
+            let range = line.split("(from ").nth(1)?.split(')').next()?;
+            let mut bounds = range.split(" to ");
+            let min = bounds.next()?.trim().parse::<f32>().ok()?;
+            let max = bounds.next()?.trim().parse::<f32>().ok()?;
+            Some((min, max))
+        })
+        // Oldest ffmpeg versions accept this much
+        .or(Some((0.5, 2.0)))
+}
+
+/// Escape value of filter option, so it can be put into filtergraph as it is.
+/// Option values and the filtergraph are escaped separately.
+pub fn escape(value: &str) -> String {
+    let mut option_value = String::new();
+    for c in value.chars() {
+        if "\\':".contains(c) {
+            option_value.push('\\');
+        }
+        option_value.push(c);
+    }
+    let mut escaped = String::new();
+    for c in option_value.chars() {
+        if "\\'[],;".contains(c) {
+            escaped.push('\\');
+        }
+        escaped.push(c);
+    }
+    escaped
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn assert_stages(speed: f32, expected: &[f32]) {
+        let stages = atempo_stages(speed, MIN_STAGE_TEMPO, MAX_STAGE_TEMPO);
+        assert_eq!(stages.len(), expected.len(), "{:?}", stages);
+        for (stage, expected) in stages.iter().zip(expected) {
+            assert!((stage - expected).abs() < 0.0001, "{:?}", stages);
+        }
+    }
+
+    #[test]
+    fn speed_split_into_stages() {
+        assert_stages(1.0, &[1.0]);
+        assert_stages(2.5, &[2.0, 1.25]);
+        assert_stages(0.1, &[0.5, 0.5, 0.5, 0.8]);
+        assert_stages(99.0, &[2.0, 2.0, 2.0, 2.0, 2.0, 2.0, 1.546875]);
+    }
+
+    #[test]
+    fn too_many_stages_rejected() {
+        assert!(validate_atempo_stages(0.5f32.powi(MAX_STAGES as i32), 0.5, 2.0).is_ok());
+        assert!(validate_atempo_stages(0.5f32.powi(MAX_STAGES as i32 + 1), 0.5, 2.0).is_err());
+        assert!(validate_atempo_stages(99.0, 0.5, 2.0).is_ok());
+        // Tempo can't be changed at all
+        assert!(validate_atempo_stages(2.0, 1.0, 1.0).is_err());
+    }
+
+    #[test]
+    fn rubberband_range() {
+        assert!(validate_stretch(Stretch::Rubberband, 0.005).is_err());
+        assert!(validate_stretch(Stretch::Rubberband, 100.0).is_ok());
+    }
+
+    #[test]
+    fn atempo_range_from_help() {
+        let help = "Filter atempo
+  Adjust audio tempo.
+atempo AVOptions:
+   tempo             <double>     ..F.A....T. set tempo scale factor (from 0.5 to 100) (default 1)
+";
+        assert_eq!(parse_atempo_range(help), Some((0.5, 100.0)));
+        // Range that can't be read falls back to what all versions accept
+        assert_eq!(
+            parse_atempo_range("Filter atempo\n   tempo <double> set tempo scale factor\n"),
+            Some((0.5, 2.0))
+        );
+        assert_eq!(parse_atempo_range("Unknown filter 'atempo'."), None);
+    }
+}
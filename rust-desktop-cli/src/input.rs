@@ -0,0 +1,56 @@
+use std::fs;
+use std::io;
+use std::path::PathBuf;
+use std::process::{Command, Stdio};
+
+use crate::tempdir::TempDir;
+
+/// Save video piped to stdin to a file in given temporary directory,
+/// and return path to it. We have to read the video several times
+/// (to get metadata, to analyze audio and to encode it), which can't be
+/// done with stdin.
+///
+/// The file gets an extension according to its container, as output
+/// format is derived from it.
+pub fn spool_stdin(tempdir: &TempDir) -> PathBuf {
+    let spooled_path = tempdir.path().join("input");
+    {
+        let mut file = fs::File::create(&spooled_path).expect("Failed to create tmp input file.");
+        io::copy(&mut io::stdin().lock(), &mut file).expect("Failed to read video from stdin.");
+    }
+
+    // Expected format: format_name=mov,mp4,m4a,3gp,3g2,mj2
+    let format_command = Command::new("ffprobe")
+        .args([
+            "-show_entries",
+            "format=format_name",
+            "-of",
+            "default=noprint_wrappers=1",
+            spooled_path.to_str().unwrap(),
+        ])
+        .stdin(Stdio::null())
+        .stderr(Stdio::null())
+        .stdout(Stdio::piped())
+        .output()
+        .expect("Failed to get container format via ffprobe.");
+    let format_string = String::from_utf8(format_command.stdout).unwrap();
+    let format_name = format_string
+        .split("=")
+        .last()
+        .unwrap()
+        .split(",")
+        .next()
+        .unwrap()
+        .trim();
+    let extension = match format_name {
+        "" => panic!("Failed to recognize format of video read from stdin."),
+        "mov" => "mp4",
+        "matroska" => "mkv",
+        "mpegts" => "ts",
+        other => other,
+    };
+
+    let input_path = tempdir.path().join(format!("input.{}", extension));
+    fs::rename(&spooled_path, &input_path).expect("Failed to rename tmp input file.");
+    input_path
+}
@@ -1,66 +1,224 @@
-use guid_create::GUID;
 use minimp3::{Decoder, Error};
+use structopt::clap::AppSettings;
 use structopt::StructOpt;
 
 use std::fs;
 use std::io::Write;
 use std::path::{Path, PathBuf};
 use std::process::{Command, Stdio};
+use std::str::FromStr;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::thread;
 
-// TODO: Remove file GUID creation for fast option, use something predictable instead.
-// Sometimes GUID filenames might clash, even if it's very unlikely to happen.
-// Even with approx. 45min long video, the chance of clash would be just something like
-// 5 : 5,316,911,983,139,663,491,615,228,241,121,400
+mod batch;
+mod chunks;
+mod config;
+mod encoding;
+mod filters;
+mod input;
+mod mpv;
+mod output;
+mod overlay;
+mod parallel;
+#[cfg(unix)]
+mod play;
+mod plot;
+mod presets;
+mod report;
+mod smartcut;
+mod stats;
+mod tempdir;
+mod timeline;
+mod watch;
+mod workdir;
+
+use encoding::EncodingSettings;
+use filters::{AudioJoin, BlendQuality, Denoise, FilterSettings, FpsMode, SilentAudio, Stretch};
+use output::OutputFormat;
+use overlay::{OverlayKind, OverlayPosition};
+use tempdir::TempDir;
 
 fn main() {
-    let mut args: Cli = Cli::from_args();
-    // Set output filename if not set by user
-    if args
-        .output
-        .to_str()
-        .expect("Failed to get output filename.")
-        .len()
-        == 0
+    // Options that are not set on command line are taken from profile
+    // and configuration files
+    let config = match config::load() {
+        Ok(config) => config,
+        Err(e) => {
+            eprintln!("{}", e);
+            std::process::exit(1);
+        }
+    };
+    let mut args: Cli = match config.parse_args() {
+        Ok(args) => args,
+        Err(e) => {
+            eprintln!("{}", e);
+            std::process::exit(1);
+        }
+    };
+    if let Some(profile) = &args.profile {
+        if !args.quiet {
+            eprintln!("Using profile {}", profile);
+        }
+    }
+    // Fill in options from encoding preset, unless they were set explicitly
+    if let Some(preset_name) = args.preset.clone() {
+        match presets::find(&preset_name, &config) {
+            Ok(preset) => preset.apply(&mut args),
+            Err(e) => {
+                eprintln!("{}", e);
+                std::process::exit(1);
+            }
+        }
+    }
+    // Summarize videos as they appear in a directory
+    if let Some(Subcommand::Watch {
+        directory,
+        interval,
+        settle,
+        ledger,
+    }) = &args.command
     {
-        if args.input.to_str().expect("Failed to get input filename.") == "-" {
-            eprintln!("Piping video in isn't supported yet. Sorry!");
-            return;
-        } else {
-            args.output = PathBuf::from(format!(
-                "{}.new.{}",
-                args.input
-                    .file_stem()
-                    .expect("Failed to get file stem from input file path.")
-                    .to_str()
-                    .unwrap(),
-                if args.fast {
-                    "mpeg"
-                } else {
-                    args.input
-                        .file_name()
-                        .expect("Failed to get file name from input file path.")
-                        .to_str()
-                        .unwrap()
-                        .split(".")
-                        .last()
-                        .unwrap()
+        watch::watch(
+            &args,
+            directory,
+            *interval,
+            *settle,
+            ledger.as_ref().map(|l| l.as_path()),
+        );
+        return;
+    }
+    if args.input.is_empty() {
+        eprintln!("Source video is not set.");
+        return;
+    }
+    // Directory or multiple videos are processed one by one, with summary at the end
+    if args.input.len() > 1 || args.input[0].is_dir() {
+        if !batch::run(&args) {
+            std::process::exit(1);
+        }
+        return;
+    }
+
+    // If video is piped in, save it to a temporary file first and use that
+    // as input. The file is removed once we're done.
+    let mut input = args.input[0].clone();
+    let input_from_stdin = input.to_str().expect("Failed to get input filename.") == "-";
+    let spooled_input = if input_from_stdin {
+        if args.mpv_script.is_some() || args.command.is_some() {
+            eprintln!("Video piped in cannot be played in mpv, since it's not saved anywhere. Save it to a file first.");
+            std::process::exit(1);
+        }
+        if !args.quiet {
+            eprintln!("Reading video from stdin");
+        }
+        let tempdir = TempDir::new();
+        input = input::spool_stdin(&tempdir);
+        Some(tempdir)
+    } else {
+        None
+    };
+    // Set output filename if not set by user
+    let output = if !args.output.as_os_str().is_empty() {
+        args.output.clone()
+    } else if input_from_stdin {
+        // Video piped in goes out the same way
+        PathBuf::from("-")
+    } else {
+        let name = batch::output_name(&args, &input);
+        match &args.output_dir {
+            Some(output_dir) => {
+                if let Err(e) = fs::create_dir_all(output_dir) {
+                    eprintln!(
+                        "Failed to create output directory {}: {}",
+                        output_dir.display(),
+                        e
+                    );
+                    std::process::exit(1);
                 }
-            ));
+                output_dir.join(name)
+            }
+            None => PathBuf::from(name),
         }
+    };
+
+    let result = summarize(&args, &input, &output, input_from_stdin);
+    // Exiting doesn't run destructors, so remove the spooled video first
+    drop(spooled_input);
+    if let Err(e) = result {
+        eprintln!("{}", e);
+        std::process::exit(1);
     }
-    // If output file exists, delete it
-    if args.output.to_str().expect("Failed to get output") != "-" {
-        if args.output.exists() {
-            fs::remove_file(&args.output).expect("Failed to delete existing output file.");
+}
+
+/// Analyze the video and render it summarized (or print stats, write
+/// mpv script, or play it, if user says so).
+///
+/// Returns how long the video is and how long it is summarized.
+fn summarize(
+    args: &Cli,
+    input: &Path,
+    output: &Path,
+    input_from_stdin: bool,
+) -> Result<batch::Summary, String> {
+    // We don't render anything if we're only asked to print stats or to play the video
+    let plays_video = matches!(args.command, Some(Subcommand::Play { .. }));
+    let renders_video = !args.show_stats && args.mpv_script.is_none() && !plays_video;
+    // Decide output format now, so we don't find out it's wrong
+    // after analyzing the whole video.
+    let encoding_settings = EncodingSettings {
+        video_codec: args.video_codec.clone(),
+        audio_codec: args.audio_codec.clone(),
+        crf: args.crf,
+        bitrate: args.bitrate.clone(),
+        audio_bitrate: args.audio_bitrate.clone(),
+        encoder_preset: args.encoder_preset.clone(),
+        threads: args.threads,
+    };
+    let output_format: Option<OutputFormat> = if renders_video {
+        let output_format = output::output_format(input, output, args.format.as_deref())?;
+        encoding_settings.validate(&output_format, args.audio)?;
+        Some(output_format)
+    } else {
+        None
+    };
+    // If output file exists, delete it. Don't touch it if we're
+    // not going to render anything.
+    if output.to_str().expect("Failed to get output") != "-" && renders_video {
+        if output.exists() {
+            fs::remove_file(output).expect("Failed to delete existing output file.");
         }
     }
     // If there is set both fast and audio option, inform user that they are incompatible.
     if args.fast && args.audio {
-        eprintln!("Audio option and fast option cannot be used together. Please use only one.");
-        eprintln!(
-            "It's strongly recommended to use the --audio option. Using only audio is faster in every case."
-        );
-        return;
+        return Err(String::from(
+            "Audio option and fast option cannot be used together. Please use only one.\nIt's strongly recommended to use the --audio option. Using only audio is faster in every case.",
+        ));
+    }
+
+    // Find out that audio can't be played at given speed before
+    // the video is analyzed
+    if renders_video && !args.smart_cut {
+        FilterSettings::new(args).validate(args.speed_loud, args.speed_silent)?;
+    }
+    if renders_video
+        && args.smart_cut
+        && !smartcut::is_cut_only(&[args.speed_loud, args.speed_silent])
+    {
+        return Err(String::from(
+            "Option --smart-cut can be used only when loud parts are played at normal speed and silent ones are dropped (-l 1 -s 100).",
+        ));
+    }
+    // Encoding options are often set in configuration, so they don't
+    // conflict with --smart-cut, user is only told they are not used
+    let sets_encoding = args.video_codec.is_some()
+        || args.audio_codec.is_some()
+        || args.crf.is_some()
+        || args.bitrate.is_some()
+        || args.audio_bitrate.is_some()
+        || args.encoder_preset.is_some()
+        || args.preset.is_some();
+    if renders_video && args.smart_cut && sets_encoding && !args.quiet {
+        eprintln!("Encoding options and presets are ignored with --smart-cut. Frames that are re-encoded use the codecs of the source video.");
     }
 
     if !args.quiet {
@@ -68,9 +226,13 @@ fn main() {
     }
 
     // Get general video metadata
-    let video_metadata: VideoMetadata = get_video_metadata(args.input.to_str().unwrap());
+    let video_metadata: VideoMetadata = get_video_metadata(input.to_str().unwrap());
 
     let mut silent_frames: Vec<bool>;
+    // Average loudness of each audio frame, and loudness under which
+    // the frame is considered silent
+    let mut sound_averages: Vec<usize> = Vec::new();
+    let silent_level: f32;
     // Detect silent frames
     {
         if !args.quiet {
@@ -80,7 +242,7 @@ fn main() {
         // Extract sound from video
         let sound = Command::new("ffmpeg")
             .arg("-i")
-            .arg(args.input.to_str().unwrap())
+            .arg(input.to_str().unwrap())
             .arg("-vn")
             .arg("-f")
             .arg("mp3")
@@ -92,7 +254,6 @@ fn main() {
             .expect("Failed to spawn sound extract process.");
         let output = sound.stdout;
         let mut sound_decoder = Decoder::new(&output[..]);
-        let mut sound_averages: Vec<usize> = Vec::new();
         let mut sound_max: usize = 0;
         let mut all_frames_data: Vec<Vec<i16>> = Vec::new();
 
@@ -120,7 +281,7 @@ fn main() {
             }
             sound_averages.push(avg);
         }
-        let silent_level = sound_max as f32 * args.silence_threshold;
+        silent_level = sound_max as f32 * args.silence_threshold;
         silent_frames = sound_averages
             .iter()
             .map(|avg| avg < &(silent_level as usize))
@@ -174,6 +335,7 @@ fn main() {
             } else {
                 args.speed_loud
             },
+            silent_frames[0],
         );
         if silent_frames[0] {
             silent_segments_count += 1;
@@ -193,6 +355,7 @@ fn main() {
                     } else {
                         args.speed_loud
                     },
+                    silent_frames[i],
                 );
                 current_speedup_loudness = silent_frames[i];
                 if silent_frames[i] {
@@ -212,45 +375,6 @@ fn main() {
         );
     }
 
-    // If user says so, estimate runtime, time saved,
-    // print it and exit.
-    if args.show_stats {
-        let video_silent_frames = silent_frames
-            .iter()
-            .filter(|f| **f)
-            .collect::<Vec<&bool>>()
-            .len();
-        let silent_percentage_of_video = video_silent_frames as f32 / silent_frames.len() as f32;
-        println!(
-            "{}% of video is silent.",
-            silent_percentage_of_video * 100.0
-        );
-        println!(
-            "It will take about {} seconds to process {} segments with flawless quality, or about {} seconds with watchable quality. Processing only audio will be almost instantaneous.",
-                video_metadata.duration_seconds as usize * 2,
-                audio_segments_speedup.len(),
-                audio_segments_speedup.len() / 3,
-        );
-        let time_total = video_metadata.duration_seconds;
-        let raw_duration_in_silence = silent_percentage_of_video * time_total;
-        let raw_duration_in_loudness = time_total - raw_duration_in_silence;
-        let mut real_duration_in_silence = raw_duration_in_silence * (1.0 / args.speed_silent);
-        let mut real_duration_in_loudness = raw_duration_in_loudness * (1.0 / args.speed_loud);
-        if args.speed_silent >= 100.0 {
-            real_duration_in_silence = 0.0;
-        }
-        if args.speed_loud >= 100.0 {
-            real_duration_in_loudness = 0.0;
-        }
-        let real_duration = real_duration_in_silence + real_duration_in_loudness;
-        println!(
-            "Estimated time saved is {} minutes ({}%).",
-            (time_total - real_duration) / 60.0,
-            ((time_total - real_duration) / time_total as f32) * 100.0
-        );
-        return;
-    }
-
     let video_segments_speedup: Vec<SpeedupRange>;
     // Figure out where to cut video
     {
@@ -265,27 +389,116 @@ fn main() {
                     (range.frame_from as f32 * rate) as usize,
                     (range.frame_to as f32 * rate) as usize,
                     range.speedup_rate,
+                    range.silent,
                 )
             })
             .collect();
     }
 
+    let segments = timeline::segments_from_ranges(&video_segments_speedup, &video_metadata);
+    let summary = batch::Summary::new(&segments);
+    if renders_video && segments.iter().all(|segment| segment.is_dropped()) {
+        return Err(String::from(
+            "All of the video would be dropped, there is nothing to render.",
+        ));
+    }
+
+    // If user says so, plot loudness and segments, so they can see
+    // how the video was split.
+    if let Some(plot_path) = &args.plot {
+        plot::write_plot(
+            plot_path,
+            input.file_name().unwrap().to_str().unwrap(),
+            &sound_averages,
+            silent_level,
+            &segments,
+            video_metadata.duration_seconds,
+        );
+    }
+
+    // If user says so, write HTML report, that can be shared
+    // together with the video.
+    if let Some(report_path) = &args.report {
+        if input_from_stdin && !args.quiet {
+            eprintln!("Video was piped in, so the report won't be able to play it.");
+        }
+        report::write_report(
+            report_path,
+            input,
+            &[
+                ("speed loud", args.speed_loud.to_string()),
+                ("speed silent", args.speed_silent.to_string()),
+                ("silence threshold", args.silence_threshold.to_string()),
+                ("frame margin", args.frame_margin.to_string()),
+            ],
+            &stats::Stats::new(&segments),
+            &sound_averages,
+            silent_level,
+            video_metadata.duration_seconds,
+        );
+    }
+
+    // If user says so, print statistics about the summarized video and exit.
+    if args.show_stats {
+        let stats = stats::Stats::new(&segments);
+        match args.stats_format {
+            StatsFormat::Text => {
+                println!(
+                    "It will take about {} seconds to process {} segments with flawless quality, or about {} seconds with watchable quality. Processing only audio will be almost instantaneous.",
+                    video_metadata.duration_seconds as usize * 2,
+                    segments.len(),
+                    segments.len() / 3,
+                );
+                stats.print_text();
+            }
+            StatsFormat::Json => stats.print_json(),
+        }
+        return Ok(summary);
+    }
+
+    // If user says so, write mpv script that plays the video summarized
+    // instead of rendering it, and exit.
+    if let Some(script_path) = &args.mpv_script {
+        mpv::write_mpv_script(
+            input,
+            script_path,
+            &video_segments_speedup,
+            &video_metadata,
+            args.quiet,
+        );
+        return Ok(summary);
+    }
+
+    // If user says so, play the video in mpv and change its speed in real time.
+    if let Some(Subcommand::Play { socket }) = &args.command {
+        #[cfg(unix)]
+        play::play(
+            input,
+            socket.as_ref().map(|s| s.as_path()),
+            &video_segments_speedup,
+            &video_metadata,
+            args.quiet,
+        );
+        #[cfg(not(unix))]
+        {
+            let _ = socket;
+            eprintln!(
+                "Playing in mpv is supported only on unix systems. Use the --mpv option instead."
+            );
+        }
+        return Ok(summary);
+    }
+
+    // Everything that doesn't render the video has returned already
+    let output_format = output_format.expect("Output format is not set.");
+
+    let filter_settings = FilterSettings::new(args);
+
     // Tell ffmpeg to do it (slower, best resolution, doesn't use temp files)
     if !args.fast {
-        let filter =
-            generate_complex_speedup_filter(&video_segments_speedup, &video_metadata, args.audio);
-        // Save filter to file
-        // Create temporary directory where we will store temporary complex filter file.
-        let tempdir_path = std::env::temp_dir().join(GUID::rand().to_string());
-        fs::DirBuilder::new()
-            .create(&tempdir_path)
-            .expect("Failed to create tmp directory.");
-        let filter_filename = tempdir_path.join("complex_filter.txt");
-        fs::write(filter_filename.to_str().unwrap(), filter).unwrap();
-            
         if !args.quiet {
             // Displaying "come back in N minutes" doesn't make sense with the --audio option, since it's really fast.
-            if !args.audio {
+            if !args.audio && !args.smart_cut {
                 eprintln!(
                     "Starting ffmpeg process. Come back in about {} minutes.",
                     (video_metadata.duration_seconds / 40.0) as usize
@@ -294,34 +507,74 @@ fn main() {
                 eprintln!("If you don't need video, use the --audio flag. It will make the process almost instantaneous.")
             }
         }
-        speedup_using_complex_filter(&args.input, &args.output, &filter_filename.to_str().unwrap(), args.audio);
-        fs::remove_dir_all(&tempdir_path).expect("Failed to remove tmp directory.");
+        if args.smart_cut {
+            smartcut::render_cut_only(args, input, output, &segments, &output_format)?;
+        } else if args.chunk_segments.is_some() || args.chunk_minutes.is_some() {
+            chunks::render_in_chunks(
+                args,
+                input,
+                output,
+                &video_segments_speedup,
+                &video_metadata,
+                &output_format,
+                &encoding_settings,
+            )?;
+        } else {
+            let filter = generate_complex_speedup_filter(
+                &video_segments_speedup,
+                &video_metadata,
+                &filter_settings,
+            );
+            // Save filter to file
+            // Create temporary directory where we will store temporary complex filter file.
+            let tempdir = TempDir::new();
+            let filter_filename = tempdir.path().join("complex_filter.txt");
+            fs::write(filter_filename.to_str().unwrap(), filter).unwrap();
+            speedup_using_complex_filter(
+                input,
+                None,
+                output,
+                filter_filename.to_str().unwrap(),
+                args.audio,
+                &output_format,
+                &encoding_settings,
+            );
+        }
     } else
     // Do the splitting, speed-uping, etc manually (fastest, worst result)
     {
-        // Create temporary directory where we will store everything.
-        let tempdir_path = std::env::temp_dir().join(GUID::rand().to_string());
-        fs::DirBuilder::new()
-            .create(&tempdir_path)
-            .expect("Failed to create tmp directory.");
+        // Create directory where we will store everything. If we were
+        // interrupted before, it already contains parts that are done.
+        let mut parameters = vec![
+            args.speed_loud.to_string(),
+            args.speed_silent.to_string(),
+            args.silence_threshold.to_string(),
+            args.frame_margin.to_string(),
+        ];
+        parameters.extend(filter_settings.parameters());
+        let tempdir_path = workdir::fast_mode_directory(input, &parameters);
+        fs::create_dir_all(&tempdir_path).expect("Failed to create tmp directory.");
+        let parts_done = (0..video_segments_speedup.len())
+            .filter(|index| part_path(&tempdir_path, *index, "mpeg").exists())
+            .count();
+        if parts_done > 0 && !args.quiet {
+            eprintln!(
+                "Resuming, {} of {} parts are already done",
+                parts_done,
+                video_segments_speedup.len()
+            );
+        }
 
         // Split and speedup videos, get these part names in order.
-        let mut video_part_paths: Vec<Option<PathBuf>> = Vec::new();
-        let mut current_part: f32 = 0.0;
-        let parts_len = video_segments_speedup.len() as f32;
-        for frame in video_segments_speedup {
-            if !args.quiet {
-                eprintln!("{}%", (current_part / parts_len) * 100.0);
-            }
-            video_part_paths.push(speedup_video_part(
-                args.input.to_str().unwrap(),
-                &frame,
-                &video_metadata,
-                &tempdir_path,
-                args.fast,
-            ));
-            current_part += 1.0;
-        }
+        let video_part_paths = speedup_video_parts(
+            input.to_str().unwrap(),
+            &video_segments_speedup,
+            &video_metadata,
+            &filter_settings,
+            &tempdir_path,
+            args.jobs(),
+            args.quiet,
+        );
 
         // Concatenate temp files
         concatenate_videos_to_file(
@@ -331,13 +584,21 @@ fn main() {
                 .map(|p| (p.as_ref().unwrap().to_str().unwrap()))
                 .collect::<Vec<&str>>(),
             &tempdir_path,
-            args.output,
+            output,
+            &filter_settings,
+            &output_format,
+            &encoding_settings,
         );
 
         fs::remove_dir_all(&tempdir_path).expect("Failed to remove tmp directory.");
     }
+
+    Ok(summary)
 }
 
+/// Frame rate used for inputs that contain only audio.
+const AUDIO_ONLY_FPS: f32 = 100.0;
+
 /// Scan video with ffprobe to determine video length, fps, and duration.
 /// This is used to sync audio and video and output estimate runtime.
 fn get_video_metadata(filename: &str) -> VideoMetadata {
@@ -380,21 +641,27 @@ fn get_video_metadata(filename: &str) -> VideoMetadata {
     // Expected format: r_frame_rate=30000/1001
     let fps_string = String::from_utf8(fps_command.stdout).unwrap();
     let fps_string = fps_string.split("=").last().unwrap();
-    let fps_string_split = (
-        fps_string.split("/").take(1).last().unwrap().trim(),
-        fps_string.split("/").last().unwrap().trim(),
-    );
-    let fps_numbers_split: (f32, f32) = (
-        fps_string_split
-            .0
-            .parse()
-            .expect(&format!("Failed to parse video fps(1) from {}", fps_string)),
-        fps_string_split
-            .1
-            .parse()
-            .expect(&format!("Failed to parse video fps(2) from {}", fps_string)),
-    );
-    let fps = fps_numbers_split.0 / fps_numbers_split.1;
+    // Audio files (such as audio piped in from youtube-dl) don't have any
+    // frames. Pretend they do, so the audio can still be cut precisely enough.
+    let fps = if fps_string.trim().is_empty() {
+        AUDIO_ONLY_FPS
+    } else {
+        let fps_string_split = (
+            fps_string.split("/").take(1).last().unwrap().trim(),
+            fps_string.split("/").last().unwrap().trim(),
+        );
+        let fps_numbers_split: (f32, f32) = (
+            fps_string_split
+                .0
+                .parse()
+                .expect(&format!("Failed to parse video fps(1) from {}", fps_string)),
+            fps_string_split
+                .1
+                .parse()
+                .expect(&format!("Failed to parse video fps(2) from {}", fps_string)),
+        );
+        fps_numbers_split.0 / fps_numbers_split.1
+    };
 
     let total_frames_command = Command::new("ffprobe")
         .args(&[
@@ -425,21 +692,53 @@ fn get_video_metadata(filename: &str) -> VideoMetadata {
     }
 }
 
+/// Split input video into parts and speed them up, running up to `jobs`
+/// ffmpeg processes at the same time. Returns paths to the parts in the same
+/// order as the ranges (`None` for parts that are dropped).
+fn speedup_video_parts(
+    input_path: &str,
+    ranges: &[SpeedupRange],
+    metadata: &VideoMetadata,
+    filter_settings: &FilterSettings,
+    tempdir_path: &Path,
+    jobs: usize,
+    quiet: bool,
+) -> Vec<Option<PathBuf>> {
+    let parts_done = AtomicUsize::new(0);
+    parallel::map(ranges, jobs, |index, range| {
+        let part_path = speedup_video_part(
+            input_path,
+            range,
+            index,
+            metadata,
+            filter_settings,
+            tempdir_path,
+            true,
+        );
+        let done = parts_done.fetch_add(1, Ordering::SeqCst) + 1;
+        if !quiet {
+            eprintln!("{}%", done as f32 / ranges.len() as f32 * 100.0);
+        }
+        part_path
+    })
+}
+
 /// Take input video, separate one part from it,
 /// speed it up and return path to the sped up video.
 ///
-/// If speed is lower than 0.5, panic.
+/// Parts are named by their index. If the part is already done
+/// (from previous run that was interrupted), it's not made again.
+///
 /// If speed is higher or equal to 100, return `None`.
 fn speedup_video_part(
     input_path: &str,
     range: &SpeedupRange,
+    index: usize,
     metadata: &VideoMetadata,
+    filter_settings: &FilterSettings,
     tempdir_path: &std::path::Path,
     force_mpeg: bool,
 ) -> Option<PathBuf> {
-    if range.speedup_rate < 0.5 {
-        panic!("Fatal error: speed rate is lower than 0.5.");
-    }
     if range.speedup_rate >= 100.0 {
         return None;
     }
@@ -456,43 +755,47 @@ fn speedup_video_part(
         input_path.split(".").last().unwrap().trim()
     };
 
-    let cut_video_filename = format!("{}.{}", GUID::rand().to_string(), extension);
-    let speedup_video_filename = format!("{}.{}", GUID::rand().to_string(), extension);
-    let cut_video_path = tempdir_path.join(Path::new(&cut_video_filename));
-    let speedup_video_path = tempdir_path.join(Path::new(&speedup_video_filename));
+    let part_path = part_path(tempdir_path, index, extension);
+    if part_path.exists() {
+        return Some(part_path);
+    }
+    // Part is written under temporary name, and renamed once it's
+    // complete, so incomplete parts are never reused.
+    let speedup_video_path = tempdir_path.join(format!("part-{:06}.tmp.{}", index, extension));
 
     let seconds_to_start_cut: f32 = range.frame_from as f32 / metadata.fps;
+    let frames = range.frame_to - range.frame_from;
+    // Audio is cut to exactly as long as the video frames (and padded with
+    // silence if it's shorter), so it doesn't drift when the parts are joined.
+    let seconds_length: f32 = frames as f32 / metadata.fps;
     let inverted_speedup_rate = 1.0 / range.speedup_rate;
 
-    // Cut video
-    let mut cut_command = Command::new("ffmpeg")
+    // Cut and speedup video in one go. Seeking before the input is accurate
+    // when transcoding, so the part starts exactly at its first frame.
+    let mut speedup_command = Command::new("ffmpeg")
         .args(&[
+            "-y",
+            "-accurate_seek",
             "-ss",
             &format!("{}", seconds_to_start_cut),
             "-i",
             input_path,
-            "-frames:v",
-            &format!("{}", range.frame_to - range.frame_from),
-            "-f",
-            extension,
-            cut_video_path.to_str().unwrap(),
-        ])
-        .stderr(Stdio::null())
-        .stdin(Stdio::null())
-        .stdout(Stdio::null())
-        .spawn()
-        .expect("Failed to cut input video.");
-    cut_command.wait().unwrap();
-
-    // Speedup video
-    let mut speedup_command = Command::new("ffmpeg")
-        .args(&[
-            "-i",
-            cut_video_path.to_str().unwrap(),
             "-filter_complex",
             &format!(
-                "[0:v]setpts={}*PTS[v];[0:a]atempo={}[a]",
-                inverted_speedup_rate, range.speedup_rate
+                "[0:v]trim=end_frame={},setpts={}*(PTS-STARTPTS){}{}[v];[0:a]asetpts=PTS-STARTPTS,apad,atrim=duration={},{}{}[a]",
+                frames,
+                inverted_speedup_rate,
+                filter_settings.video_chain(range.speedup_rate, seconds_length / range.speedup_rate),
+                // Parts are encoded at the output frame rate, so frames
+                // are blended before the encoding drops any of them
+                filter_settings
+                    .joined_video_filters()
+                    .iter()
+                    .map(|filter| format!(",{}", filter))
+                    .collect::<String>(),
+                seconds_length,
+                filter_settings.audio_chain(range.speedup_rate, range.silent),
+                filter_settings.audio_fades(seconds_length / range.speedup_rate, false)
             ),
             "-map",
             "[v]",
@@ -508,24 +811,40 @@ fn speedup_video_part(
         .spawn()
         .expect("Failed to spawn speedup video process.");
 
-    speedup_command.wait().unwrap();
+    let status = speedup_command.wait().unwrap();
+    if !status.success() {
+        panic!("Failed to speed up part {} of the video.", index);
+    }
+    fs::rename(&speedup_video_path, &part_path).expect("Failed to save part of the video.");
+
+    Some(part_path)
+}
 
-    Some(speedup_video_path)
+/// Path to the sped up part of the video with given index.
+fn part_path(tempdir_path: &Path, index: usize, extension: &str) -> PathBuf {
+    tempdir_path.join(format!("part-{:06}.{}", index, extension))
 }
 
 /// Create file that will contain all video names in given directory.
 /// Afterwards, concatenate all those videos using ffmpeg to output path.
-fn concatenate_videos_to_file(filenames: Vec<&str>, tempdir_path: &PathBuf, output_path: PathBuf) {
+fn concatenate_videos_to_file(
+    filenames: Vec<&str>,
+    tempdir_path: &PathBuf,
+    output_path: &Path,
+    filter_settings: &FilterSettings,
+    output_format: &OutputFormat,
+    encoding_settings: &EncodingSettings,
+) {
     // Create "files" file, which will contain list of filenames. We
     // will then pass this file to ffmpeg. We cannot do this normally,
     // since there is a limit on number of arguments ffmpeg can process
     // the old way.
-    let extension = filenames.first().unwrap().split(".").last().unwrap();
 
     let filenames_register_path = tempdir_path.join("files.txt");
     std::fs::OpenOptions::new()
         .create(true)
         .write(true)
+        .truncate(true)
         .open(&filenames_register_path)
         .expect("Failed to open file register.")
         .write_all(
@@ -538,7 +857,15 @@ fn concatenate_videos_to_file(filenames: Vec<&str>, tempdir_path: &PathBuf, outp
         )
         .expect("Failed to write to file register.");
 
-    Command::new("ffmpeg")
+    // Parts are scaled down and their audio normalized only now,
+    // in the same pass as the encoding. Frame rate is already changed.
+    let mut video_filters: Vec<String> = Vec::new();
+    if let Some(height) = filter_settings.max_height {
+        video_filters.push(scale_filter(height));
+    }
+    let audio_filters = filter_settings.joined_audio_filters();
+
+    let status = Command::new("ffmpeg")
         .args(&[
             "-f",
             "concat",
@@ -546,10 +873,20 @@ fn concatenate_videos_to_file(filenames: Vec<&str>, tempdir_path: &PathBuf, outp
             "0",
             "-i",
             tempdir_path.join("files.txt").to_str().unwrap(),
-            "-f",
-            extension,
-            output_path.to_str().unwrap(),
         ])
+        .args(if video_filters.is_empty() {
+            Vec::new()
+        } else {
+            vec![String::from("-vf"), video_filters.join(",")]
+        })
+        .args(if audio_filters.is_empty() {
+            Vec::new()
+        } else {
+            vec![String::from("-af"), audio_filters.join(",")]
+        })
+        .args(encoding_settings.args(false))
+        .args(output_format.args())
+        .arg(output_path.to_str().unwrap())
         .stdin(Stdio::null())
         .stdout(Stdio::inherit())
         .stderr(Stdio::null())
@@ -557,51 +894,44 @@ fn concatenate_videos_to_file(filenames: Vec<&str>, tempdir_path: &PathBuf, outp
         .expect("Failed to run video concatenate process")
         .wait()
         .expect("Failed to concatenate video files.");
+    // Keep the parts if this fails, so they can be reused next time
+    if !status.success() {
+        panic!("Failed to concatenate video files.");
+    }
 }
 
+/// Render the video with complex filter. If input range is set (start and
+/// length in seconds), only that part of the input is read, and times in
+/// the filter are relative to its start.
 fn speedup_using_complex_filter(
-    input: &PathBuf,
-    output: &PathBuf,
+    input: &Path,
+    input_range: Option<(f32, f32)>,
+    output: &Path,
     complex_filter_filename: &str,
     audio_only: bool,
+    output_format: &OutputFormat,
+    encoding_settings: &EncodingSettings,
 ) {
-    let args: Vec<&str>;
-    if audio_only {
-        args = vec![
-            "-i",
-            input.to_str().unwrap(),
-            "-vn",
-            "-threads",
-            "8",
-            "-filter_complex_script",
-            complex_filter_filename,
-            "-f",
-            input.to_str().unwrap().split(".").last().unwrap(),
-            "-movflags",
-            "frag_keyframe+empty_moov",
-            output.to_str().unwrap(),
-        ];
-    } else {
-        args = vec![
-            "-i",
-            input.to_str().unwrap(),
-            "-preset",
-            "faster",
-            "-crf",
-            "27",
-            "-threads",
-            "8",
-            "-filter_complex_script",
-            complex_filter_filename,
-            "-f",
-            input.to_str().unwrap().split(".").last().unwrap(),
-            "-movflags",
-            "frag_keyframe+empty_moov",
-            output.to_str().unwrap(),
-        ];
+    let mut args: Vec<String> = Vec::new();
+    if let Some((start, length)) = input_range {
+        args.extend(vec![
+            String::from("-ss"),
+            start.to_string(),
+            String::from("-t"),
+            length.to_string(),
+        ]);
     }
+    args.extend(vec![
+        String::from("-i"),
+        input.to_str().unwrap().to_string(),
+        String::from("-filter_complex_script"),
+        complex_filter_filename.to_string(),
+    ]);
+    args.extend(encoding_settings.args(audio_only));
+    args.extend(output_format.args().iter().map(|arg| arg.to_string()));
+    args.push(output.to_str().unwrap().to_string());
 
-    Command::new("ffmpeg")
+    let status = Command::new("ffmpeg")
         .args(args)
         .stdin(Stdio::null())
         .stdout(Stdio::inherit())
@@ -610,6 +940,9 @@ fn speedup_using_complex_filter(
         .expect("Failed to spawn speedup via complex filter.")
         .wait()
         .expect("Failed to run speedup via complex filter.");
+    if !status.success() {
+        panic!("Failed to run speedup via complex filter.");
+    }
 }
 
 /// Generate ffmpeg complex filter that will speed up the video.
@@ -629,76 +962,215 @@ fn speedup_using_complex_filter(
 /// [0:a]atrim=0.75:1,asetpts=PTS-STARTPTS,atempo=2[a3];
 /// [v1][a1][v2][a2][v3][a3]concat=n=3:v=1:a=1
 /// ```
+///
+/// Every video part is scaled down to maximum height, denoised, frames of
+/// fast parts are blended, and speed overlay is drawn over them, if these
+/// are set. Output frame rate and loudness of audio are changed once
+/// the parts are joined, in the same filtergraph.
+///
+/// Speed of audio is changed by filters chosen by `--stretch-loud`
+/// and `--stretch-silent`, and audio of silent segments can be muted
+/// (see `FilterSettings::audio_chain`).
+///
+/// When audio is crossfaded, audio of every segment but the last one is
+/// cut longer by the crossfade, padded with silence if the input ends, and
+/// the segments are joined by `acrossfade` instead of `concat`. The overlap
+/// is removed by the crossfade, so audio stays in sync with the video.
 fn generate_complex_speedup_filter(
     ranges: &Vec<SpeedupRange>,
     metadata: &VideoMetadata,
-    audio_only: bool,
+    filter_settings: &FilterSettings,
 ) -> String {
+    let audio_only = filter_settings.audio_only;
+    // Segments with speed of 100 or more are dropped
+    let ranges: Vec<&SpeedupRange> = ranges
+        .iter()
+        .filter(|range| range.frame_to - range.frame_from != 0 && range.speedup_rate < 100.0)
+        .collect();
+    let crossfade = filter_settings.audio_overlap() > 0.0 && ranges.len() > 1;
+    // Crossfade can't be longer than either of the segments it joins,
+    // so each join overlaps at most as much as the shorter one is long
+    let durations: Vec<f32> = ranges
+        .iter()
+        .map(|range| (range.frame_to - range.frame_from) as f32 / metadata.fps / range.speedup_rate)
+        .collect();
+    let overlaps: Vec<f32> = durations
+        .windows(2)
+        .map(|pair| filter_settings.audio_overlap().min(pair[0]).min(pair[1]))
+        .collect();
     let mut complex_filter = String::new();
-    let mut idx: usize = 1;
-    for range in ranges {
-        if range.frame_to - range.frame_from == 0 {
-            continue;
-        }
+    for (i, range) in ranges.iter().enumerate() {
+        let idx = i + 1;
         let seconds_from: f32 = range.frame_from as f32 / metadata.fps;
         let seconds_to: f32 = range.frame_to as f32 / metadata.fps;
         let inverted_speedup = 1.0 / range.speedup_rate;
         if !audio_only {
             complex_filter.push_str(&format!(
-                "[0:v]trim={}:{},setpts={}*(PTS-STARTPTS)[v{}];",
-                seconds_from, seconds_to, inverted_speedup, idx
+                "[0:v]trim={}:{},setpts={}*(PTS-STARTPTS)",
+                seconds_from, seconds_to, inverted_speedup
+            ));
+            if let Some(height) = filter_settings.max_height {
+                complex_filter.push_str(&format!(",{}", scale_filter(height)));
+            }
+            complex_filter.push_str(&filter_settings.video_chain(
+                range.speedup_rate,
+                (seconds_to - seconds_from) / range.speedup_rate,
+            ));
+            complex_filter.push_str(&format!("[v{}];", idx));
+        }
+        let duration = durations[i];
+        if crossfade {
+            // Last segment isn't cut longer, as there's nothing to overlap with
+            let overlap = overlaps.get(i).cloned().unwrap_or(0.0);
+            let seconds_to = seconds_to + overlap * range.speedup_rate;
+            let duration = duration + overlap;
+            complex_filter.push_str(&format!(
+                "[0:a]atrim={}:{},asetpts=PTS-STARTPTS,{},apad,atrim=duration={}[a{}];",
+                seconds_from,
+                seconds_to,
+                filter_settings.audio_chain(range.speedup_rate, range.silent),
+                duration,
+                idx
+            ));
+        } else {
+            complex_filter.push_str(&format!(
+                "[0:a]atrim={}:{},asetpts=PTS-STARTPTS,{}{}[a{}];",
+                seconds_from,
+                seconds_to,
+                filter_settings.audio_chain(range.speedup_rate, range.silent),
+                filter_settings.audio_fades(duration, true),
+                idx
             ));
         }
-        complex_filter.push_str(&format!(
-            "[0:a]atrim={}:{},asetpts=PTS-STARTPTS,atempo={}[a{}];",
-            seconds_from, seconds_to, range.speedup_rate, idx
-        ));
-        idx += 1;
     }
-    for i in 1..idx {
+
+    if crossfade {
+        // Video is joined on its own, and audio by chain of crossfades
+        if !audio_only {
+            for i in 1..=ranges.len() {
+                complex_filter.push_str(&format!("[v{}]", i));
+            }
+            complex_filter.push_str(&format!("concat=n={}:v=1:a=0", ranges.len()));
+            for filter in filter_settings.joined_video_filters() {
+                complex_filter.push_str(&format!(",{}", filter));
+            }
+            complex_filter.push(';');
+        }
+        complex_filter.push_str("[a1]");
+        for i in 2..=ranges.len() {
+            complex_filter.push_str(&format!(
+                "[a{}]acrossfade=d={}:c1=tri:c2=tri",
+                i,
+                overlaps[i - 2]
+            ));
+            if i < ranges.len() {
+                complex_filter.push_str(&format!("[x{}];[x{}]", i, i));
+            }
+        }
+        for filter in filter_settings.joined_audio_filters() {
+            complex_filter.push_str(&format!(",{}", filter));
+        }
+        return complex_filter;
+    }
+
+    for i in 1..=ranges.len() {
         if audio_only {
             complex_filter.push_str(&format!("[a{}]", i));
         } else {
             complex_filter.push_str(&format!("[v{}][a{}]", i, i));
         }
     }
+    let joined_video_filters = filter_settings.joined_video_filters();
+    let joined_audio_filters = filter_settings.joined_audio_filters();
     if audio_only {
-        complex_filter.push_str(&format!("concat=n={}:a=1:v=0", idx - 1));
+        complex_filter.push_str(&format!("concat=n={}:a=1:v=0", ranges.len()));
+        for filter in joined_audio_filters {
+            complex_filter.push_str(&format!(",{}", filter));
+        }
     } else {
-        complex_filter.push_str(&format!("concat=n={}:v=1:a=1", idx - 1));
+        complex_filter.push_str(&format!("concat=n={}:v=1:a=1", ranges.len()));
+        if !joined_video_filters.is_empty() || !joined_audio_filters.is_empty() {
+            // Outputs of concat can be labeled only both at once,
+            // the one without filters is passed through
+            complex_filter.push_str(&format!(
+                "[joinedv][joineda];[joinedv]{};[joineda]{}",
+                if joined_video_filters.is_empty() {
+                    String::from("null")
+                } else {
+                    joined_video_filters.join(",")
+                },
+                if joined_audio_filters.is_empty() {
+                    String::from("anull")
+                } else {
+                    joined_audio_filters.join(",")
+                }
+            ));
+        }
     }
 
     complex_filter
 }
 
+/// ffmpeg filter that scales video down to given height, keeping
+/// aspect ratio. Smaller videos are left as they are.
+fn scale_filter(max_height: u32) -> String {
+    format!("scale=-2:min(ih\\,{})", max_height)
+}
+
 #[derive(StructOpt)]
 #[structopt(
     name = "Video Summarizer",
     about = "Take a video, and change it's speed, depending on silent and loud parts.",
-    rename_all = "kebab-case"
+    rename_all = "kebab-case",
+    raw(setting = "AppSettings::SubcommandsNegateReqs")
 )]
 struct Cli {
-    /// Source video
+    /// Source videos
     ///
     /// Path to source video. Video must be
-    /// parsable by FFMPEG.
-    #[structopt(parse(from_os_str))]
-    input: std::path::PathBuf,
+    /// parsable by FFMPEG. If there are more videos, or a directory,
+    /// all of them are summarized and summary table is printed at the end.
+    /// This is not needed with the watch command.
+    #[structopt(parse(from_os_str), raw(required = "true"))]
+    input: Vec<PathBuf>,
     /// Output file
     ///
-    /// This is by default "old_filename.new.extension".
-    #[structopt(parse(from_os_str), short = "o", default_value = "")]
+    /// This is by default "old_filename.new.extension" (see --name-template).
+    /// This can be set only if there is single source video.
+    #[structopt(parse(from_os_str), short = "o", long = "output", default_value = "")]
     output: std::path::PathBuf,
+    /// Directory to write output files to.
+    ///
+    /// Subdirectories of source directories are created in it as well.
+    /// Output files are by default written next to the source videos.
+    #[structopt(long = "output-dir", parse(from_os_str))]
+    output_dir: Option<PathBuf>,
+    /// How output files are named, unless output file is set.
+    ///
+    /// "{stem}" is replaced with source filename without extension,
+    /// "{ext}" with output extension and "{parent}" with name of the directory
+    /// the source video is in.
+    #[structopt(long = "name-template", default_value = "{stem}.new.{ext}")]
+    name_template: String,
+    /// Only summarize files in source directories that match this
+    /// pattern, such as "*.mp4" or "2020-*/**/*.mkv".
+    ///
+    /// "*" matches anything except "/", "**" matches anything, and "?" matches
+    /// single character. Patterns without "/" are matched against filename only.
+    /// Can be used multiple times. By default, common video and audio files are summarized.
+    #[structopt(long = "glob", raw(number_of_values = "1"))]
+    glob: Vec<String>,
+    /// Number of videos to summarize at the same time, when there are more of them.
+    #[structopt(long = "parallel", default_value = "1")]
+    parallel: usize,
     /// Video speed when loud sound is detected.
     ///
-    /// This has to be at least 0.5.
     /// If this is larger than or equal to 100, loud parts of
     /// the video will be dropped completely.
     #[structopt(long = "speed-loud", short = "l", default_value = "1.5")]
     speed_loud: f32,
     /// Video speed when no loud sound was detected.
     ///
-    /// This has to be at least 0.5.
     /// If this is larger than or equal to 100, silent parts
     /// of the video will be dropped completely.
     #[structopt(long = "speed-silent", short = "s", default_value = "5")]
@@ -724,31 +1196,317 @@ struct Cli {
     /// This includes estimated run time and time saved on the video.
     #[structopt(long = "stats")]
     show_stats: bool,
+    /// Format of the statistics printed with the --stats option.
+    ///
+    /// JSON output contains list of all segments as well.
+    #[structopt(
+        long = "stats-format",
+        default_value = "text",
+        raw(possible_values = "&[\"text\", \"json\"]")
+    )]
+    stats_format: StatsFormat,
+    /// Plot loudness of the audio, silence threshold and how the
+    /// video was split into segments, and save it to this file.
+    ///
+    /// The plot is SVG, unless the filename ends with ".png".
+    /// Use this together with --stats to tune the silence threshold
+    /// and frame margin without rendering the video.
+    #[structopt(long = "plot", parse(from_os_str))]
+    plot: Option<PathBuf>,
+    /// Write HTML report with loudness plot, statistics, list of all
+    /// segments and parameters used, and save it to this file.
+    ///
+    /// The report plays the source video, and clicking a segment
+    /// seeks to it. Keep the report next to the video when sharing it.
+    #[structopt(long = "report", parse(from_os_str))]
+    report: Option<PathBuf>,
     /// Encode resulting video in MPEG. This will probably make resolution
     /// worse, but will speed up the whole process a LOT.
     ///
-    /// This option is obsolete. It doesn't support piping out
-    /// (immediatelly, you'll have to wait for the processing to end first)
-    /// and it doesn't support the --audio option.
+    /// This option is obsolete. When piping out, the output starts only
+    /// after all the parts are processed, and it doesn't support
+    /// the --audio option.
     #[structopt(long = "fast")]
     fast: bool,
+    /// Number of parts of the video to process at the same time, with
+    /// the --fast option or when rendering in chunks.
+    ///
+    /// This is by default the number of CPU cores.
+    #[structopt(long = "jobs", short = "j")]
+    jobs: Option<usize>,
+    /// Render the video in chunks of at most this many segments, and join
+    /// them at the end.
+    ///
+    /// Use this for very long videos with thousands of segments, where ffmpeg
+    /// struggles with single huge filter. Chunks are rendered in parallel (see --jobs).
+    #[structopt(long = "chunk-segments")]
+    chunk_segments: Option<usize>,
+    /// Render the video in chunks of at most this many minutes of the source
+    /// video, and join them at the end.
+    #[structopt(long = "chunk-minutes")]
+    chunk_minutes: Option<f32>,
+    /// Only cut out the silent parts, and copy the rest of the video
+    /// without re-encoding it. Only frames around cuts that are not
+    /// at a keyframe are re-encoded, with the codecs of the source video.
+    ///
+    /// This works only when loud parts are played at normal speed and silent
+    /// ones are dropped (-l 1 -s 100). Filter options cannot be used with it,
+    /// and encoding options are ignored.
+    #[structopt(
+        long = "smart-cut",
+        raw(
+            conflicts_with_all = r#"&["fast", "audio", "max-height", "output-fps", "speed-overlay", "motion-blend-speed", "audio-join", "stretch-loud", "stretch-silent", "silent-audio", "denoise", "highpass", "loudnorm", "chunk-segments", "chunk-minutes"]"#
+        )
+    )]
+    smart_cut: bool,
+    /// With --smart-cut, move cuts that are at most this many seconds from
+    /// a keyframe to the keyframe, so the frames around don't have to be re-encoded.
+    #[structopt(long = "keyframe-tolerance", default_value = "0.5")]
+    keyframe_tolerance: f32,
     /// Keep only audio, and drop all video frames. This will
     /// make processing almost instantaneous.
     #[structopt(long = "audio")]
     audio: bool,
+    /// Show speed over segments that are sped up: "text" (such as "▶▶ 5x")
+    /// or "bar" (progress bar that fills as the segment plays).
+    ///
+    /// It's shown only over segments that are played at other
+    /// than --overlay-base-speed.
+    #[structopt(long = "speed-overlay")]
+    speed_overlay: Option<OverlayKind>,
+    /// Text of the speed overlay. "{speed}" is replaced by speed of the segment.
+    #[structopt(long = "overlay-text", default_value = "▶▶ {speed}x")]
+    overlay_text: String,
+    /// Position of the speed overlay: "top-left", "top-right", "bottom-left",
+    /// "bottom-right" or "center". Bar is shown at the top or at the bottom.
+    #[structopt(long = "overlay-position", default_value = "top-right")]
+    overlay_position: OverlayPosition,
+    /// Font of the speed overlay text, either font file or name of the font.
+    #[structopt(long = "overlay-font")]
+    overlay_font: Option<String>,
+    /// Opacity of the speed overlay, from 0 to 1.
+    #[structopt(long = "overlay-opacity", default_value = "0.8")]
+    overlay_opacity: f32,
+    /// Speed of segments that don't have the speed overlay. This is by
+    /// default the speed of loud segments.
+    #[structopt(long = "overlay-base-speed")]
+    overlay_base_speed: Option<f32>,
+    /// Frame rate of the output video.
+    ///
+    /// Sped up segments have many more frames per second than the source
+    /// video. By default they are all kept, which wastes bits, or the encoder
+    /// drops some of them unpredictably.
+    #[structopt(long = "output-fps")]
+    output_fps: Option<f32>,
+    /// How to reach the --output-fps: "drop" (drop or duplicate frames)
+    /// or "blend" (blend neighbouring frames, which is slower).
+    #[structopt(long = "fps-mode", default_value = "drop")]
+    fps_mode: FpsMode,
+    /// Blend frames of segments that are at least this fast, so they look
+    /// smooth, like a time-lapse, instead of jumpy. Use it together
+    /// with --output-fps.
+    #[structopt(long = "motion-blend-speed")]
+    motion_blend_speed: Option<f32>,
+    /// How many frames are blended together: "low" (at most 4), "medium"
+    /// (at most 8) or "high" (at most 16). More frames look smoother,
+    /// but take longer to render.
+    #[structopt(long = "motion-blend-quality", default_value = "medium")]
+    motion_blend_quality: BlendQuality,
+    /// How to change speed of loud segments' audio: "atempo",
+    /// "rubberband" or "resample".
+    ///
+    /// Rubberband sounds better with music, but only some ffmpeg builds have it.
+    /// Resample is the cheapest, but it changes pitch as well.
+    #[structopt(long = "stretch-loud", default_value = "atempo")]
+    stretch_loud: Stretch,
+    /// How to change speed of silent segments' audio: "atempo",
+    /// "rubberband" or "resample".
+    #[structopt(long = "stretch-silent", default_value = "atempo")]
+    stretch_silent: Stretch,
+    /// What to do with audio of silent segments: "keep", "mute", "noise"
+    /// (replace it with quiet noise), or number of decibels to make it
+    /// quieter by, such as "20".
+    ///
+    /// Sped up background noise can be distracting.
+    #[structopt(long = "silent-audio", default_value = "keep")]
+    silent_audio: SilentAudio,
+    /// How to join audio of segments: "cut", "fade" (every segment
+    /// fades in and out) or "crossfade" (segments overlap).
+    ///
+    /// Hard cuts can be heard as clicks. Crossfades are not possible
+    /// with the --fast option, so segments fade in and out instead.
+    #[structopt(long = "audio-join", default_value = "cut")]
+    audio_join: AudioJoin,
+    /// Length of fades or crossfades at joins of segments, in milliseconds.
+    #[structopt(long = "audio-join-ms", default_value = "20")]
+    audio_join_ms: u32,
+    /// Output container format, such as "mp4", "mkv" or "mpegts".
+    ///
+    /// This is by default derived from the output filename,
+    /// or from the source video if the output filename doesn't have an extension.
+    #[structopt(long = "format")]
+    format: Option<String>,
+    /// Video codec, as named by ffmpeg, such as "libx264", "libx265" or "libvpx-vp9".
+    ///
+    /// This is by default chosen by ffmpeg, depending on the output format.
+    #[structopt(long = "video-codec")]
+    video_codec: Option<String>,
+    /// Audio codec, as named by ffmpeg, such as "aac", "libopus" or "flac".
+    ///
+    /// This is by default chosen by ffmpeg, depending on the output format.
+    #[structopt(long = "audio-codec")]
+    audio_codec: Option<String>,
+    /// Constant rate factor of the video. Lower is better quality
+    /// and bigger file.
+    ///
+    /// This is 27 by default, unless --bitrate is set.
+    #[structopt(long = "crf", raw(conflicts_with = "\"bitrate\""))]
+    crf: Option<u8>,
+    /// Target video bitrate, such as "2M" or "800k".
+    #[structopt(long = "bitrate")]
+    bitrate: Option<String>,
+    /// Target audio bitrate, such as "128k".
+    #[structopt(long = "audio-bitrate")]
+    audio_bitrate: Option<String>,
+    /// Encoder preset. Slower presets produce smaller files
+    /// with the same quality.
+    ///
+    /// Possible values depend on the encoder, for libx264 these are
+    /// ultrafast, superfast, veryfast, faster, fast, medium, slow, slower and veryslow.
+    /// This is "faster" by default.
+    #[structopt(long = "encoder-preset")]
+    encoder_preset: Option<String>,
+    /// Scale the video down to this height, if it's higher.
+    #[structopt(long = "max-height")]
+    max_height: Option<u32>,
+    /// Remove noise from the video: "light", "medium" or "strong".
+    #[structopt(long = "denoise")]
+    denoise: Option<Denoise>,
+    /// Cut off audio under this frequency, in Hz, such as rumble
+    /// and hum. 80 works well for speech.
+    #[structopt(long = "highpass")]
+    highpass: Option<u32>,
+    /// Normalize loudness of the audio (to -16 LUFS).
+    #[structopt(long = "loudnorm")]
+    loudnorm: bool,
+    /// Use named set of output settings: container, codecs,
+    /// resolution and quality.
+    ///
+    /// Built-in presets are "archive" (high quality H.264/AAC in MKV),
+    /// "mobile" (small 720p MP4), "podcast" (Opus audio only)
+    /// and "lossless-audio" (FLAC). More can be defined in the
+    /// configuration file. Options set explicitly take precedence over the preset.
+    #[structopt(long = "preset")]
+    preset: Option<String>,
+    /// Use named profile from the configuration file.
+    ///
+    /// Profile sets default values of any options, such as speeds and
+    /// silence threshold for a lecture series. Options set on the command line
+    /// take precedence over the profile, and the profile takes precedence
+    /// over the rest of the configuration.
+    #[structopt(long = "profile")]
+    profile: Option<String>,
+    /// Number of threads ffmpeg uses to encode the output.
+    #[structopt(long = "threads", default_value = "8")]
+    threads: usize,
+    /// Do not render anything, write mpv script that plays
+    /// the source video summarized instead.
+    ///
+    /// This is a Lua script that changes playback speed
+    /// (run it with "mpv --script=script.lua video"). If some parts
+    /// of the video are dropped (speed >= 100), this is an EDL file
+    /// that can be played directly with mpv instead.
+    #[structopt(long = "mpv", parse(from_os_str))]
+    mpv_script: Option<PathBuf>,
+    #[structopt(subcommand)]
+    command: Option<Subcommand>,
+}
+
+impl Cli {
+    /// Number of parts of the video to process at the same time.
+    fn jobs(&self) -> usize {
+        self.jobs.unwrap_or_else(|| {
+            thread::available_parallelism()
+                .map(|jobs| jobs.get())
+                .unwrap_or(1)
+        })
+    }
+}
+
+#[derive(StructOpt)]
+enum Subcommand {
+    /// Play the source video in mpv, and change its speed in real time.
+    ///
+    /// Silent and loud parts are played at their speeds, and parts that
+    /// would be dropped are skipped. This requires mpv to be installed.
+    #[structopt(name = "play")]
+    Play {
+        /// mpv IPC socket
+        ///
+        /// If there is mpv already listening on this socket, control it instead
+        /// of launching a new one.
+        #[structopt(long = "socket", parse(from_os_str))]
+        socket: Option<PathBuf>,
+    },
+    /// Watch directory, and summarize new videos once they stop growing.
+    ///
+    /// Videos are written to --output-dir ("summarized" in the watched
+    /// directory by default), together with JSON file with stats of each of
+    /// them. Videos that were summarized are recorded in a ledger, so they are
+    /// not summarized again after restart. Use --profile to choose the settings.
+    #[structopt(name = "watch")]
+    Watch {
+        /// Directory to watch
+        #[structopt(parse(from_os_str))]
+        directory: PathBuf,
+        /// How often to look for new videos, in seconds.
+        #[structopt(long = "interval", default_value = "10")]
+        interval: u64,
+        /// How long a video must not change to be considered
+        /// complete, in seconds.
+        #[structopt(long = "settle", default_value = "30")]
+        settle: u64,
+        /// Ledger of summarized videos. This is by default
+        /// ".video-summarizer-ledger.json" in the output directory.
+        #[structopt(long = "ledger", parse(from_os_str))]
+        ledger: Option<PathBuf>,
+    },
+}
+
+enum StatsFormat {
+    Text,
+    Json,
+}
+impl FromStr for StatsFormat {
+    type Err = String;
+
+    fn from_str(format: &str) -> Result<StatsFormat, String> {
+        match format {
+            "text" => Ok(StatsFormat::Text),
+            "json" => Ok(StatsFormat::Json),
+            _ => Err(format!("Unknown stats format: {}", format)),
+        }
+    }
 }
 
 struct SpeedupRange {
     frame_from: usize,
     frame_to: usize,
     speedup_rate: f32,
+    silent: bool,
 }
 impl SpeedupRange {
-    pub fn new(frame_from: usize, frame_to: usize, speedup_rate: f32) -> SpeedupRange {
+    pub fn new(
+        frame_from: usize,
+        frame_to: usize,
+        speedup_rate: f32,
+        silent: bool,
+    ) -> SpeedupRange {
         SpeedupRange {
             frame_from,
             frame_to,
             speedup_rate,
+            silent,
         }
     }
 }
@@ -758,3 +1516,109 @@ struct VideoMetadata {
     duration_seconds: f32,
     total_frames: usize,
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn crossfade_is_clamped_to_shorter_segment() {
+        let args = Cli::from_iter(&[
+            "video-summarizer",
+            "--audio",
+            "--audio-join",
+            "crossfade",
+            "--audio-join-ms",
+            "100",
+            "--stretch-loud",
+            "resample",
+            "--stretch-silent",
+            "resample",
+            "input",
+        ]);
+        let metadata = VideoMetadata {
+            fps: 10.0,
+            duration_seconds: 2.1,
+            total_frames: 21,
+        };
+        // Segments are 1 s, 1 s and 0.05 s long once sped up
+        let ranges = vec![
+            SpeedupRange::new(0, 10, 1.0, false),
+            SpeedupRange::new(10, 20, 1.0, false),
+            SpeedupRange::new(20, 21, 2.0, true),
+        ];
+        let filter =
+            generate_complex_speedup_filter(&ranges, &metadata, &FilterSettings::new(&args));
+        let segments: Vec<&str> = filter.split(';').collect();
+        assert!(segments[0].starts_with("[0:a]atrim=0:1.1,"));
+        assert!(segments[0].ends_with("atrim=duration=1.1[a1]"));
+        assert!(segments[1].starts_with("[0:a]atrim=1:2.05,"));
+        assert!(segments[1].ends_with("atrim=duration=1.05[a2]"));
+        // Last segment is neither cut longer, nor padded
+        assert!(segments[2].starts_with("[0:a]atrim=2:2.1,"));
+        assert!(segments[2].ends_with("atrim=duration=0.05[a3]"));
+        assert_eq!(segments[3], "[a1][a2]acrossfade=d=0.1:c1=tri:c2=tri[x2]");
+        assert_eq!(segments[4], "[x2][a3]acrossfade=d=0.05:c1=tri:c2=tri");
+    }
+
+    #[test]
+    fn dropped_segments_are_not_rendered() {
+        // Speed like this couldn't be stretched by any method
+        let args = Cli::from_iter(&[
+            "video-summarizer",
+            "--audio",
+            "-s",
+            "1000000",
+            "--stretch-loud",
+            "resample",
+            "input",
+        ]);
+        let metadata = VideoMetadata {
+            fps: 10.0,
+            duration_seconds: 3.0,
+            total_frames: 30,
+        };
+        let ranges = vec![
+            SpeedupRange::new(0, 10, 1.0, false),
+            SpeedupRange::new(10, 20, 1000000.0, true),
+            SpeedupRange::new(20, 30, 1.0, false),
+        ];
+        let filter =
+            generate_complex_speedup_filter(&ranges, &metadata, &FilterSettings::new(&args));
+        assert_eq!(
+            filter,
+            "[0:a]atrim=0:1,asetpts=PTS-STARTPTS,aresample=48000,asetrate=48000,aresample=48000[a1];\
+             [0:a]atrim=2:3,asetpts=PTS-STARTPTS,aresample=48000,asetrate=48000,aresample=48000[a2];\
+             [a1][a2]concat=n=2:a=1:v=0"
+        );
+    }
+
+    #[test]
+    fn rubberband_not_asked_for_dropped_speed() {
+        let args = Cli::from_iter(&[
+            "video-summarizer",
+            "-l",
+            "2",
+            "-s",
+            "150",
+            "--stretch-loud",
+            "rubberband",
+            "--stretch-silent",
+            "rubberband",
+            "input",
+        ]);
+        let metadata = VideoMetadata {
+            fps: 10.0,
+            duration_seconds: 2.0,
+            total_frames: 20,
+        };
+        let ranges = vec![
+            SpeedupRange::new(0, 10, 2.0, false),
+            SpeedupRange::new(10, 20, 150.0, true),
+        ];
+        let filter =
+            generate_complex_speedup_filter(&ranges, &metadata, &FilterSettings::new(&args));
+        assert!(filter.contains("rubberband=tempo=2"));
+        assert!(!filter.contains("rubberband=tempo=150"));
+    }
+}
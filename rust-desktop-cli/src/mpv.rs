@@ -0,0 +1,237 @@
+use std::fs;
+use std::path::Path;
+
+use crate::{SpeedupRange, VideoMetadata};
+
+/// Write file that lets mpv play the input video summarized,
+/// without rendering anything.
+///
+/// If no part of the video is to be dropped, this is a Lua script
+/// that changes playback speed based on current playback position.
+/// Otherwise, this is an EDL file that contains only the parts of the video
+/// that are to be kept.
+pub fn write_mpv_script(
+    input: &Path,
+    script_path: &Path,
+    ranges: &[SpeedupRange],
+    metadata: &VideoMetadata,
+    quiet: bool,
+) {
+    let drops_segments = ranges.iter().any(|range| range.speedup_rate >= 100.0);
+    let script = if drops_segments {
+        generate_edl(input, ranges, metadata, quiet)
+    } else {
+        generate_lua_script(input, ranges, metadata)
+    };
+    fs::write(script_path, script).expect("Failed to write mpv script.");
+    if !quiet {
+        if drops_segments {
+            eprintln!(
+                "Play the result with: mpv {}",
+                script_path.to_str().unwrap()
+            );
+        } else {
+            eprintln!(
+                "Play the result with: mpv --script={} {}",
+                script_path.to_str().unwrap(),
+                input.to_str().unwrap()
+            );
+        }
+    }
+}
+
+/// Generate mpv Lua script that changes playback speed, based on where
+/// the playback currently is. This does the same thing as the firefox
+/// extension does in `change_video_rate`.
+///
+/// The script looks like this:
+///
+/// ```lua
+/// local filename = "lecture.mp4"
+/// local timeline = {
+///     {0, 1.5},
+///     {2.5, 5},
+///     {4.1, 1.5},
+/// }
+/// ...
+/// mp.observe_property("time-pos", "number", change_video_rate)
+/// ```
+fn generate_lua_script(input: &Path, ranges: &[SpeedupRange], metadata: &VideoMetadata) -> String {
+    let mut script = String::new();
+    script.push_str("-- Generated by video-summarizer.\n");
+    script.push_str(&format!(
+        "local filename = \"{}\"\n",
+        escape_lua_string(input.file_name().unwrap().to_str().unwrap())
+    ));
+    script.push_str("local timeline = {\n");
+    for range in ranges {
+        if range.frame_to - range.frame_from == 0 {
+            continue;
+        }
+        script.push_str(&format!(
+            "    {{{}, {}}},\n",
+            range.frame_from as f32 / metadata.fps,
+            range.speedup_rate
+        ));
+    }
+    script.push_str("}\n");
+    script.push_str(LUA_SCRIPT_BODY);
+    script
+}
+
+/// Part of the Lua script that doesn't depend on the video.
+/// Timeline is sorted, so we can binary search the current segment
+/// instead of going through all of them on every `time-pos` change.
+const LUA_SCRIPT_BODY: &str = r#"
+local enabled = false
+local current_rate = nil
+
+local function segment_rate(seconds)
+    local low, high = 1, #timeline
+    local rate = timeline[1][2]
+    while low <= high do
+        local middle = math.floor((low + high) / 2)
+        if timeline[middle][1] <= seconds then
+            rate = timeline[middle][2]
+            low = middle + 1
+        else
+            high = middle - 1
+        end
+    end
+    return rate
+end
+
+local function change_video_rate(_, seconds)
+    if not enabled or seconds == nil or #timeline == 0 then
+        return
+    end
+    local next_rate = segment_rate(seconds)
+    if next_rate ~= current_rate then
+        mp.set_property_number("speed", next_rate)
+        current_rate = next_rate
+    end
+end
+
+-- Only change speed of the video this script was generated for.
+mp.register_event("file-loaded", function()
+    enabled = mp.get_property("filename") == filename
+    current_rate = nil
+end)
+
+mp.observe_property("time-pos", "number", change_video_rate)
+"#;
+
+/// Generate mpv EDL file, that contains only parts of the video that
+/// are not dropped. EDL cannot change playback speed, so if the kept
+/// parts are supposed to be sped up, user has to do it themselves.
+///
+/// The file looks like this:
+///
+/// ```text
+/// # mpv EDL v0
+/// %25%/home/user/lecture.mp4,0,2.5
+/// %25%/home/user/lecture.mp4,4.1,10.3
+/// ```
+fn generate_edl(
+    input: &Path,
+    ranges: &[SpeedupRange],
+    metadata: &VideoMetadata,
+    quiet: bool,
+) -> String {
+    // EDL paths are relative to the EDL file, so use absolute path instead.
+    let input = fs::canonicalize(input).expect("Failed to get absolute path of input file.");
+    let input = input.to_str().unwrap();
+    let mut kept_speeds: Vec<f32> = Vec::new();
+
+    let mut edl = String::from("# mpv EDL v0\n");
+    for range in ranges {
+        if range.speedup_rate >= 100.0 || range.frame_to - range.frame_from == 0 {
+            continue;
+        }
+        if !kept_speeds.contains(&range.speedup_rate) {
+            kept_speeds.push(range.speedup_rate);
+        }
+        // Filename is length-prefixed, so it may contain commas.
+        edl.push_str(&format!(
+            "%{}%{},{},{}\n",
+            input.len(),
+            input,
+            range.frame_from as f32 / metadata.fps,
+            (range.frame_to - range.frame_from) as f32 / metadata.fps
+        ));
+    }
+
+    if !quiet {
+        if kept_speeds.len() == 1 && kept_speeds[0] != 1.0 {
+            eprintln!(
+                "EDL cannot change playback speed. Start mpv with --speed={} to speed up the video.",
+                kept_speeds[0]
+            );
+        } else if kept_speeds.len() > 1 {
+            eprintln!("EDL cannot change playback speed. Kept parts of the video will play at normal speed.");
+        }
+    }
+
+    edl
+}
+
+fn escape_lua_string(text: &str) -> String {
+    text.replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ranges() -> Vec<SpeedupRange> {
+        vec![
+            SpeedupRange::new(0, 25, 1.5, false),
+            SpeedupRange::new(25, 25, 5.0, true),
+            SpeedupRange::new(25, 41, 100.0, true),
+            SpeedupRange::new(41, 103, 1.5, false),
+        ]
+    }
+
+    fn metadata() -> VideoMetadata {
+        VideoMetadata {
+            fps: 10.0,
+            duration_seconds: 10.3,
+            total_frames: 103,
+        }
+    }
+
+    #[test]
+    fn lua_timeline() {
+        let script =
+            generate_lua_script(Path::new("dir/my \"lecture\".mp4"), &ranges(), &metadata());
+        assert!(script.contains("local filename = \"my \\\"lecture\\\".mp4\"\n"));
+        // Empty range is left out
+        assert!(script
+            .contains("local timeline = {\n    {0, 1.5},\n    {2.5, 100},\n    {4.1, 1.5},\n}\n"));
+        assert!(script.ends_with(LUA_SCRIPT_BODY));
+    }
+
+    #[test]
+    fn edl_keeps_only_kept_parts() {
+        let input = std::env::temp_dir().join(format!(
+            "video-summarizer-mpv-test-{},1.mp4",
+            std::process::id()
+        ));
+        fs::write(&input, "").unwrap();
+        let edl = generate_edl(&input, &ranges(), &metadata(), true);
+        let absolute = fs::canonicalize(&input).unwrap();
+        fs::remove_file(&input).unwrap();
+
+        let absolute = absolute.to_str().unwrap();
+        assert_eq!(
+            edl,
+            format!(
+                "# mpv EDL v0\n%{len}%{path},0,2.5\n%{len}%{path},4.1,6.2\n",
+                len = absolute.len(),
+                path = absolute
+            )
+        );
+    }
+}
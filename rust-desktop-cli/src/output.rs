@@ -0,0 +1,224 @@
+use std::path::Path;
+use std::process::{Command, Stdio};
+
+/// Container the output is written in, together with any
+/// ffmpeg options the container needs.
+pub struct OutputFormat {
+    /// Name of the ffmpeg muxer, as passed to `-f`
+    pub muxer: String,
+    /// Additional muxer options, such as flags that make MP4 streamable
+    pub muxer_args: Vec<String>,
+}
+impl OutputFormat {
+    /// ffmpeg arguments that select this format. These go right before the output path.
+    pub fn args(&self) -> Vec<&str> {
+        let mut args = vec!["-f", self.muxer.as_str()];
+        args.extend(self.muxer_args.iter().map(|arg| arg.as_str()));
+        args
+    }
+}
+
+/// Decide which container to write the output in.
+///
+/// If user chose the container, it's used. Otherwise, when writing to
+/// a file, the container is taken from output filename
+/// extension (or from input filename, if output doesn't have any).
+/// When writing to stdout, the output has to be streamable, as ffmpeg
+/// cannot seek back and finish the file. MP4 is fragmented in that case,
+/// and containers that cannot be streamed at all are refused.
+pub fn output_format(
+    input: &Path,
+    output: &Path,
+    format: Option<&str>,
+) -> Result<OutputFormat, String> {
+    let to_stdout = output.to_str() == Some("-");
+    let muxer = if let Some(format) = format {
+        muxer_for_extension(format)
+    } else {
+        let extension = if to_stdout {
+            extension_of(input)
+        } else {
+            extension_of(output).or_else(|| extension_of(input))
+        };
+        match extension {
+            Some(extension) => muxer_for_extension(&extension),
+            None => {
+                return Err(String::from(
+                    "Failed to determine output format. Use output filename with an extension, or the --format option.",
+                ))
+            }
+        }
+    };
+
+    if !to_stdout {
+        return Ok(OutputFormat {
+            muxer,
+            muxer_args: Vec::new(),
+        });
+    }
+
+    match muxer.as_str() {
+        // MP4 has to be fragmented, otherwise it needs to seek back
+        // and write the index at the beginning of the file.
+        "mp4" | "mov" => Ok(OutputFormat {
+            muxer: String::from("mp4"),
+            muxer_args: vec![
+                String::from("-movflags"),
+                String::from("frag_keyframe+empty_moov"),
+            ],
+        }),
+        "matroska" | "webm" | "mpegts" | "mpeg" | "flv" | "ogg" | "opus" | "mp3" | "adts"
+        | "flac" | "wav" => Ok(OutputFormat {
+            muxer,
+            muxer_args: Vec::new(),
+        }),
+        _ => Err(format!(
+            "Container \"{}\" cannot be streamed to stdout. Write output to a file instead, or choose MP4, MKV or MPEG-TS with the --format option.",
+            muxer
+        )),
+    }
+}
+
+/// Ask ffmpeg which video codec it encodes into given container,
+/// unless told otherwise.
+pub fn default_video_codec(muxer: &str) -> Option<String> {
+    let help = Command::new("ffmpeg")
+        .args(["-hide_banner", "-h", &format!("muxer={}", muxer)])
+        .stdin(Stdio::null())
+        .stderr(Stdio::null())
+        .stdout(Stdio::piped())
+        .output()
+        .expect("Failed to run ffmpeg. Is it installed?");
+    parse_default_video_codec(&String::from_utf8_lossy(&help.stdout))
+}
+
+fn parse_default_video_codec(help: &str) -> Option<String> {
+    // Expected format:
+    //     Default video codec: h264.
+    help.lines()
+        .find_map(|line| line.trim().strip_prefix("Default video codec:"))
+        .map(|codec| codec.trim().trim_end_matches('.').to_string())
+        .filter(|codec| !codec.is_empty())
+}
+
+fn extension_of(path: &Path) -> Option<String> {
+    path.extension()
+        .and_then(|extension| extension.to_str())
+        .map(|extension| extension.to_lowercase())
+}
+
+/// Translate filename extension to ffmpeg muxer name. Extensions
+/// we don't know (and muxer names themselves) are passed to ffmpeg as they are.
+fn muxer_for_extension(extension: &str) -> String {
+    let muxer = match extension {
+        "mp4" | "m4v" | "m4a" => "mp4",
+        "mkv" | "mka" => "matroska",
+        "ts" | "m2ts" | "mts" => "mpegts",
+        "mpeg" | "mpg" => "mpeg",
+        "oga" => "ogg",
+        "aac" => "adts",
+        "wmv" | "wma" => "asf",
+        other => other,
+    };
+    String::from(muxer)
+}
+
+/// Filename extension for given muxer, used to name the output
+/// if user didn't name it.
+pub fn extension_for_muxer(muxer: &str) -> String {
+    let extension = match muxer {
+        "matroska" => "mkv",
+        "mpegts" => "ts",
+        "adts" => "aac",
+        "asf" => "wmv",
+        "ipod" => "m4a",
+        other => other,
+    };
+    String::from(extension)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn format(
+        input: &str,
+        output: &str,
+        format: Option<&str>,
+    ) -> Result<(String, Vec<String>), String> {
+        output_format(Path::new(input), Path::new(output), format)
+            .map(|format| (format.muxer, format.muxer_args))
+    }
+
+    #[test]
+    fn file_output() {
+        assert_eq!(
+            format("in.mp4", "out.MKV", None),
+            Ok((String::from("matroska"), vec![]))
+        );
+        // Container that can't be streamed is fine in a file
+        assert_eq!(
+            format("in.mp4", "out.avi", None),
+            Ok((String::from("avi"), vec![]))
+        );
+        // Output without extension takes the one of input
+        assert_eq!(
+            format("in.ts", "out", None),
+            Ok((String::from("mpegts"), vec![]))
+        );
+        assert_eq!(
+            format("in.mp4", "out.mp4", Some("mkv")),
+            Ok((String::from("matroska"), vec![]))
+        );
+        assert!(format("in", "out", None).is_err());
+    }
+
+    #[test]
+    fn stdout_mp4_is_fragmented() {
+        assert_eq!(
+            format("in.mp4", "-", None),
+            Ok((
+                String::from("mp4"),
+                vec![
+                    String::from("-movflags"),
+                    String::from("frag_keyframe+empty_moov")
+                ]
+            ))
+        );
+        assert_eq!(format("in.mov", "-", None).unwrap().0, "mp4");
+    }
+
+    #[test]
+    fn stdout_mkv() {
+        assert_eq!(
+            format("in.mkv", "-", None),
+            Ok((String::from("matroska"), vec![]))
+        );
+        assert_eq!(
+            format("in.avi", "-", Some("mkv")),
+            Ok((String::from("matroska"), vec![]))
+        );
+    }
+
+    #[test]
+    fn default_video_codec_from_help() {
+        let help = "Muxer matroska [Matroska]:
+    Common extensions: mkv.
+    Mime type: video/x-matroska.
+    Default video codec: h264.
+    Default audio codec: vorbis.
+    Default subtitle codec: ass.
+";
+        assert_eq!(parse_default_video_codec(help), Some(String::from("h264")));
+        let help = "Muxer mp3 [MP3 (MPEG audio layer 3)]:
+    Default audio codec: mp3.
+";
+        assert_eq!(parse_default_video_codec(help), None);
+    }
+
+    #[test]
+    fn stdout_avi_is_refused() {
+        assert!(format("in.avi", "-", None).is_err());
+        assert!(format("in.mp4", "-", Some("avi")).is_err());
+    }
+}
@@ -0,0 +1,144 @@
+use std::str::FromStr;
+
+use crate::filters::escape;
+use crate::Cli;
+
+/// Number of steps the progress bar fills in.
+const BAR_STEPS: usize = 10;
+
+/// What is shown over segments that are sped up.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum OverlayKind {
+    /// Text with the speed, such as "▶▶ 5x".
+    Text,
+    /// Bar that fills as the segment plays.
+    Bar,
+}
+impl FromStr for OverlayKind {
+    type Err = String;
+
+    fn from_str(kind: &str) -> Result<OverlayKind, String> {
+        match kind {
+            "text" => Ok(OverlayKind::Text),
+            "bar" => Ok(OverlayKind::Bar),
+            _ => Err(format!("Unknown speed overlay: {}", kind)),
+        }
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum OverlayPosition {
+    TopLeft,
+    TopRight,
+    BottomLeft,
+    BottomRight,
+    Center,
+}
+impl FromStr for OverlayPosition {
+    type Err = String;
+
+    fn from_str(position: &str) -> Result<OverlayPosition, String> {
+        match position {
+            "top-left" => Ok(OverlayPosition::TopLeft),
+            "top-right" => Ok(OverlayPosition::TopRight),
+            "bottom-left" => Ok(OverlayPosition::BottomLeft),
+            "bottom-right" => Ok(OverlayPosition::BottomRight),
+            "center" => Ok(OverlayPosition::Center),
+            _ => Err(format!("Unknown overlay position: {}", position)),
+        }
+    }
+}
+
+/// Indicator drawn over segments that are played at other than base speed,
+/// so viewers know the video is fast-forwarded.
+#[derive(Debug)]
+pub struct SpeedOverlay {
+    kind: OverlayKind,
+    /// Text, where "{speed}" is replaced by speed of the segment.
+    text: String,
+    position: OverlayPosition,
+    /// Font file, or name of the font.
+    font: Option<String>,
+    opacity: f32,
+    base_speed: f32,
+}
+impl SpeedOverlay {
+    /// Overlay set by the user, if any.
+    pub fn new(args: &Cli) -> Option<SpeedOverlay> {
+        Some(SpeedOverlay {
+            kind: args.speed_overlay?,
+            text: args.overlay_text.clone(),
+            position: args.overlay_position,
+            font: args.overlay_font.clone(),
+            opacity: args.overlay_opacity.clamp(0.0, 1.0),
+            base_speed: args.overlay_base_speed.unwrap_or(args.speed_loud),
+        })
+    }
+
+    /// Filters that draw the overlay over segment played at given speed,
+    /// which is `duration` seconds long after it's sped up. This is empty
+    /// if the segment is played at base speed.
+    pub fn filters(&self, speed: f32, duration: f32) -> String {
+        if (speed - self.base_speed).abs() < 0.001 {
+            return String::new();
+        }
+        match self.kind {
+            OverlayKind::Text => self.text_filter(speed),
+            OverlayKind::Bar => self.bar_filters(duration),
+        }
+    }
+
+    fn text_filter(&self, speed: f32) -> String {
+        // Margin is relative to height, so it's the same on both axes
+        let (x, y) = match self.position {
+            OverlayPosition::TopLeft => ("h/30", "h/30"),
+            OverlayPosition::TopRight => ("w-tw-h/30", "h/30"),
+            OverlayPosition::BottomLeft => ("h/30", "h-th-h/30"),
+            OverlayPosition::BottomRight => ("w-tw-h/30", "h-th-h/30"),
+            OverlayPosition::Center => ("(w-tw)/2", "(h-th)/2"),
+        };
+        let font = match &self.font {
+            // Font names don't contain path separators or extensions
+            Some(font) if font.contains('/') || font.contains('\\') || font.contains('.') => {
+                format!(":fontfile={}", escape(font))
+            }
+            Some(font) => format!(":font={}", escape(font)),
+            None => String::new(),
+        };
+        format!(
+            ",drawtext=text={}:expansion=none{}:fontsize=h/16:fontcolor=white@{}:box=1:boxcolor=black@{}:boxborderw=10:x={}:y={}",
+            escape(&self.text.replace("{speed}", &speed.to_string())),
+            font,
+            self.opacity,
+            self.opacity / 2.0,
+            escape(x),
+            escape(y)
+        )
+    }
+
+    /// Progress bar that fills in steps. Boxes can't change their size over
+    /// time, so there is one for each step, shown only during the step.
+    fn bar_filters(&self, duration: f32) -> String {
+        let y = match self.position {
+            OverlayPosition::TopLeft | OverlayPosition::TopRight => "0",
+            _ => "ih-ih/60",
+        };
+        let step = duration / BAR_STEPS as f32;
+        (1..=BAR_STEPS)
+            .map(|i| {
+                format!(
+                    ",drawbox=x=0:y={}:w=iw*{}/{}:h=ih/60:color=white@{}:t=fill:enable={}",
+                    y,
+                    i,
+                    BAR_STEPS,
+                    self.opacity,
+                    escape(&format!(
+                        "between(t,{},{})",
+                        step * (i - 1) as f32,
+                        step * i as f32
+                    ))
+                )
+            })
+            .collect()
+    }
+}
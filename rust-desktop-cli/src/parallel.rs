@@ -0,0 +1,51 @@
+use std::sync::{mpsc, Arc, Mutex};
+use std::thread;
+
+/// Call `f` for every item, running up to `jobs` of them at the same time.
+/// Results are returned in the same order as the items.
+///
+/// Items are handed out through a bounded queue, so they are taken only
+/// as fast as workers process them. If `f` panics, the panic is propagated
+/// once other workers finish.
+pub fn map<T, R, F>(items: &[T], jobs: usize, f: F) -> Vec<R>
+where
+    T: Sync,
+    R: Send,
+    F: Fn(usize, &T) -> R + Sync,
+{
+    let results: Mutex<Vec<Option<R>>> = Mutex::new(items.iter().map(|_| None).collect());
+    let jobs = jobs.max(1);
+    let (sender, receiver) = mpsc::sync_channel::<(usize, &T)>(jobs);
+    // Receiver is dropped once all workers end, even if they panic,
+    // so sending doesn't block forever
+    let receiver = Arc::new(Mutex::new(receiver));
+    thread::scope(|scope| {
+        for _ in 0..jobs {
+            let receiver = Arc::clone(&receiver);
+            let results = &results;
+            let f = &f;
+            scope.spawn(move || loop {
+                let next = receiver.lock().unwrap().recv();
+                let (index, item) = match next {
+                    Ok(next) => next,
+                    Err(_) => break,
+                };
+                let result = f(index, item);
+                results.lock().unwrap()[index] = Some(result);
+            });
+        }
+        drop(receiver);
+        for item in items.iter().enumerate() {
+            if sender.send(item).is_err() {
+                break;
+            }
+        }
+        drop(sender);
+    });
+    results
+        .into_inner()
+        .unwrap()
+        .into_iter()
+        .map(|result| result.expect("Item was not processed."))
+        .collect()
+}
@@ -0,0 +1,433 @@
+use guid_create::GUID;
+use serde_json::{json, Value};
+
+use std::io::{BufRead, BufReader, Write};
+use std::os::unix::net::UnixStream;
+use std::path::Path;
+use std::process::{Command, Stdio};
+use std::thread;
+use std::time::Duration;
+
+use crate::timeline::{self, Segment};
+use crate::{SpeedupRange, VideoMetadata};
+
+const TIME_POS_OBSERVER_ID: u64 = 1;
+const PAUSE_OBSERVER_ID: u64 = 2;
+
+/// Play input video in mpv and change playback speed in real time,
+/// depending on which segment is currently being played. Segments
+/// with speed higher than or equal to 100 are skipped.
+///
+/// If mpv already listens on given IPC socket, control it instead of
+/// launching a new one. It's up to the user to make sure it's playing the input video.
+pub fn play(
+    input: &Path,
+    socket: Option<&Path>,
+    ranges: &[SpeedupRange],
+    metadata: &VideoMetadata,
+    quiet: bool,
+) {
+    let segments = timeline::segments_from_ranges(ranges, metadata);
+
+    let socket_path = match socket {
+        Some(socket) => socket.to_path_buf(),
+        None => std::env::temp_dir().join(format!("{}.sock", GUID::rand())),
+    };
+
+    // Attach to running mpv if there is one, otherwise launch our own.
+    let mut mpv_process = None;
+    let stream = match UnixStream::connect(&socket_path) {
+        Ok(stream) => {
+            if !quiet {
+                eprintln!("Attached to mpv at {}", socket_path.to_str().unwrap());
+            }
+            stream
+        }
+        Err(_) => {
+            if !quiet {
+                eprintln!("Starting mpv");
+            }
+            mpv_process = Some(
+                Command::new("mpv")
+                    .arg(format!(
+                        "--input-ipc-server={}",
+                        socket_path.to_str().unwrap()
+                    ))
+                    .arg("--")
+                    .arg(input)
+                    .stdin(Stdio::null())
+                    .stdout(Stdio::null())
+                    .stderr(Stdio::null())
+                    .spawn()
+                    .expect("Failed to spawn mpv."),
+            );
+            connect_with_retry(&socket_path)
+        }
+    };
+
+    let reader = BufReader::new(stream.try_clone().expect("Failed to clone mpv socket."));
+    Controller::new(reader, stream, segments).run();
+
+    if let Some(mut mpv_process) = mpv_process {
+        mpv_process.wait().expect("Failed to wait for mpv to exit.");
+        // We created the socket, so we clean it up as well
+        if socket.is_none() {
+            let _ = std::fs::remove_file(&socket_path);
+        }
+    }
+}
+
+/// mpv creates the IPC socket some time after it's started,
+/// so try connecting to it for a few seconds.
+fn connect_with_retry(socket_path: &Path) -> UnixStream {
+    for _ in 0..100 {
+        if let Ok(stream) = UnixStream::connect(socket_path) {
+            return stream;
+        }
+        thread::sleep(Duration::from_millis(100));
+    }
+    panic!("Failed to connect to mpv IPC socket.");
+}
+
+/// Talks to mpv over its JSON IPC protocol. It observes playback position
+/// and sets playback speed accordingly, or seeks over segments that
+/// are to be dropped.
+///
+/// This works with anything that talks the mpv protocol, so it can be
+/// pointed to a fake mpv server as well.
+pub struct Controller<R: BufRead, W: Write> {
+    reader: R,
+    writer: W,
+    segments: Vec<Segment>,
+    position: Option<f32>,
+    current_speed: Option<f32>,
+    paused: bool,
+    /// Set while a seek is in progress, as mpv might still report
+    /// positions from before the seek.
+    seeking: bool,
+    /// Index of the dropped segment we last seeked out of. We don't try
+    /// again if mpv lands slightly before the end of it.
+    last_skipped_segment: Option<usize>,
+}
+
+impl<R: BufRead, W: Write> Controller<R, W> {
+    pub fn new(reader: R, writer: W, segments: Vec<Segment>) -> Controller<R, W> {
+        Controller {
+            reader,
+            writer,
+            segments,
+            position: None,
+            current_speed: None,
+            paused: false,
+            seeking: false,
+            last_skipped_segment: None,
+        }
+    }
+
+    /// Control playback until the file ends, or mpv closes the connection.
+    pub fn run(&mut self) {
+        self.send(json!({ "command": ["observe_property", TIME_POS_OBSERVER_ID, "time-pos"] }));
+        self.send(json!({ "command": ["observe_property", PAUSE_OBSERVER_ID, "pause"] }));
+
+        let mut line = String::new();
+        loop {
+            line.clear();
+            match self.reader.read_line(&mut line) {
+                Ok(0) | Err(_) => break,
+                Ok(_) => {}
+            }
+            // Replies to our commands don't contain anything we need,
+            // and we don't care about messages we don't understand.
+            let message: Value = match serde_json::from_str(&line) {
+                Ok(message) => message,
+                Err(_) => continue,
+            };
+            match message["event"].as_str() {
+                Some("property-change") => match message["name"].as_str() {
+                    Some("time-pos") => {
+                        self.position = message["data"].as_f64().map(|p| p as f32);
+                        self.update();
+                    }
+                    Some("pause") => {
+                        self.paused = message["data"].as_bool().unwrap_or(false);
+                        self.update();
+                    }
+                    _ => {}
+                },
+                // User (or we) started seeking
+                Some("seek") => self.seeking = true,
+                Some("playback-restart") => {
+                    self.seeking = false;
+                    self.update();
+                }
+                Some("end-file") | Some("shutdown") => break,
+                _ => {}
+            }
+        }
+    }
+
+    /// Set speed of the current segment, or skip it if it's dropped.
+    /// Dropped segments are not skipped while paused, so user can
+    /// look around without being thrown elsewhere.
+    fn update(&mut self) {
+        let position = match self.position {
+            Some(position) => position,
+            None => return,
+        };
+        if self.seeking {
+            return;
+        }
+        let index = match self.segment_index_at(position) {
+            Some(index) => index,
+            None => return,
+        };
+        let segment = &self.segments[index];
+
+        if segment.is_dropped() {
+            if self.paused || self.last_skipped_segment == Some(index) {
+                return;
+            }
+            let target = segment.to;
+            self.last_skipped_segment = Some(index);
+            self.seeking = true;
+            self.send(json!({ "command": ["seek", target, "absolute+exact"] }));
+        } else {
+            self.last_skipped_segment = None;
+            if self.current_speed != Some(segment.speed) {
+                let speed = segment.speed;
+                self.current_speed = Some(speed);
+                self.send(json!({ "command": ["set_property", "speed", speed] }));
+            }
+        }
+    }
+
+    /// Find the last segment that starts at or before given position.
+    fn segment_index_at(&self, position: f32) -> Option<usize> {
+        if self.segments.is_empty() {
+            return None;
+        }
+        match self
+            .segments
+            .binary_search_by(|segment| segment.from.partial_cmp(&position).unwrap())
+        {
+            Ok(index) => Some(index),
+            Err(0) => Some(0),
+            Err(index) => Some(index - 1),
+        }
+    }
+
+    /// Send command to mpv. If mpv is gone, we find out when
+    /// reading from it, so errors are ignored here.
+    fn send(&mut self, command: Value) {
+        let _ = writeln!(self.writer, "{}", command);
+        let _ = self.writer.flush();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+    use std::os::unix::net::UnixListener;
+
+    fn segments() -> Vec<Segment> {
+        vec![
+            Segment {
+                from: 0.0,
+                to: 5.0,
+                speed: 1.0,
+                silent: false,
+            },
+            Segment {
+                from: 5.0,
+                to: 10.0,
+                speed: 8.0,
+                silent: true,
+            },
+            Segment {
+                from: 10.0,
+                to: 15.0,
+                speed: 100.0,
+                silent: true,
+            },
+            Segment {
+                from: 15.0,
+                to: 20.0,
+                speed: 1.0,
+                silent: false,
+            },
+        ]
+    }
+
+    fn time_pos(position: f64) -> Value {
+        json!({ "event": "property-change", "id": TIME_POS_OBSERVER_ID, "name": "time-pos", "data": position })
+    }
+
+    fn pause(paused: bool) -> Value {
+        json!({ "event": "property-change", "id": PAUSE_OBSERVER_ID, "name": "pause", "data": paused })
+    }
+
+    fn event(name: &str) -> Value {
+        json!({ "event": name })
+    }
+
+    /// Feed messages to the controller as mpv would, and return
+    /// the commands it sent back, without the initial observers.
+    fn commands(messages: &[Value]) -> Vec<Value> {
+        let input: String = messages.iter().map(|m| format!("{}\n", m)).collect();
+        let mut output: Vec<u8> = Vec::new();
+        Controller::new(Cursor::new(input), &mut output, segments()).run();
+        String::from_utf8(output)
+            .unwrap()
+            .lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .filter(|command: &Value| command["command"][0] != "observe_property")
+            .collect()
+    }
+
+    fn set_speed(speed: f32) -> Value {
+        json!({ "command": ["set_property", "speed", speed] })
+    }
+
+    #[test]
+    fn sets_speed_when_entering_segment() {
+        let sent = commands(&[time_pos(1.0), time_pos(2.0), time_pos(6.0), time_pos(7.0)]);
+        assert_eq!(sent, vec![set_speed(1.0), set_speed(8.0)]);
+    }
+
+    #[test]
+    fn seeks_over_dropped_segment() {
+        let sent = commands(&[time_pos(9.0), time_pos(10.5)]);
+        assert_eq!(
+            sent,
+            vec![
+                set_speed(8.0),
+                json!({ "command": ["seek", 15.0, "absolute+exact"] })
+            ]
+        );
+    }
+
+    #[test]
+    fn sends_nothing_while_paused() {
+        let sent = commands(&[pause(true), time_pos(11.0), time_pos(12.0)]);
+        assert!(sent.is_empty());
+    }
+
+    #[test]
+    fn does_not_seek_again_when_landing_in_skipped_segment() {
+        let sent = commands(&[
+            time_pos(10.5),
+            // Positions from before the seek are ignored
+            time_pos(10.6),
+            event("seek"),
+            time_pos(10.7),
+            event("playback-restart"),
+            // mpv landed slightly before the end of the dropped segment
+            time_pos(14.99),
+            time_pos(15.1),
+        ]);
+        assert_eq!(
+            sent,
+            vec![
+                json!({ "command": ["seek", 15.0, "absolute+exact"] }),
+                set_speed(1.0)
+            ]
+        );
+    }
+
+    /// Fake mpv that listens on a Unix socket. It replies to every command,
+    /// sends given events after the observers are set up, and returns
+    /// all commands it got.
+    fn fake_mpv(listener: UnixListener, events: Vec<Value>) -> thread::JoinHandle<Vec<Value>> {
+        thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            let mut reader = BufReader::new(stream.try_clone().unwrap());
+            let mut commands: Vec<Value> = Vec::new();
+            let mut read_command = |stream: &mut UnixStream| {
+                let mut line = String::new();
+                reader.read_line(&mut line).unwrap();
+                let command: Value = serde_json::from_str(&line).unwrap();
+                writeln!(stream, "{}", json!({ "error": "success" })).unwrap();
+                command
+            };
+            commands.push(read_command(&mut stream));
+            commands.push(read_command(&mut stream));
+            for event in events {
+                writeln!(stream, "{}", event).unwrap();
+                if event["event"] == "property-change" {
+                    commands.push(read_command(&mut stream));
+                }
+            }
+            writeln!(stream, "{}", event("end-file")).unwrap();
+            commands
+        })
+    }
+
+    fn socket_path(name: &str) -> std::path::PathBuf {
+        std::env::temp_dir().join(format!(
+            "video-summarizer-play-test-{}-{}.sock",
+            name,
+            std::process::id()
+        ))
+    }
+
+    #[test]
+    fn attaches_to_running_mpv() {
+        let socket = socket_path("attach");
+        let _ = std::fs::remove_file(&socket);
+        let listener = UnixListener::bind(&socket).unwrap();
+        // Both events start a new segment, so each is answered by a command
+        let server = fake_mpv(listener, vec![time_pos(1.0), time_pos(6.0)]);
+
+        let ranges = vec![
+            SpeedupRange::new(0, 50, 1.0, false),
+            SpeedupRange::new(50, 100, 8.0, true),
+        ];
+        let metadata = VideoMetadata {
+            fps: 10.0,
+            duration_seconds: 10.0,
+            total_frames: 100,
+        };
+        play(
+            Path::new("input.mp4"),
+            Some(&socket),
+            &ranges,
+            &metadata,
+            true,
+        );
+        let commands = server.join().unwrap();
+        let _ = std::fs::remove_file(&socket);
+
+        assert_eq!(
+            commands,
+            vec![
+                json!({ "command": ["observe_property", TIME_POS_OBSERVER_ID, "time-pos"] }),
+                json!({ "command": ["observe_property", PAUSE_OBSERVER_ID, "pause"] }),
+                set_speed(1.0),
+                set_speed(8.0),
+            ]
+        );
+    }
+
+    #[test]
+    fn waits_for_socket() {
+        let socket = socket_path("retry");
+        let _ = std::fs::remove_file(&socket);
+        // mpv creates the socket a while after it's started
+        let server_socket = socket.clone();
+        let server = thread::spawn(move || {
+            thread::sleep(Duration::from_millis(300));
+            let listener = UnixListener::bind(&server_socket).unwrap();
+            listener.accept().unwrap();
+        });
+        connect_with_retry(&socket);
+        server.join().unwrap();
+        let _ = std::fs::remove_file(&socket);
+    }
+
+    #[test]
+    fn stops_at_end_of_file() {
+        let sent = commands(&[time_pos(1.0), event("end-file"), time_pos(6.0)]);
+        assert_eq!(sent, vec![set_speed(1.0)]);
+    }
+}
@@ -0,0 +1,594 @@
+use std::fs;
+use std::io::BufWriter;
+use std::path::Path;
+
+use crate::timeline::Segment;
+
+pub const WIDTH: f32 = 1600.0;
+const HEIGHT: f32 = 500.0;
+pub const MARGIN_LEFT: f32 = 70.0;
+pub const MARGIN_RIGHT: f32 = 20.0;
+const MARGIN_TOP: f32 = 50.0;
+const MARGIN_BOTTOM: f32 = 40.0;
+/// Loudness is plotted in decibels relative to the loudest frame.
+/// Anything quieter than this is at the bottom of the plot.
+const MIN_DB: f32 = -60.0;
+/// Time axis ticks, in seconds. We pick the first one that doesn't
+/// produce too many ticks.
+const TIME_TICKS: [f32; 14] = [
+    1.0, 2.0, 5.0, 10.0, 15.0, 30.0, 60.0, 120.0, 300.0, 600.0, 900.0, 1800.0, 3600.0, 7200.0,
+];
+const MAX_TIME_TICKS: f32 = 15.0;
+
+const BLACK: Color = Color(0, 0, 0);
+const GRAY: Color = Color(127, 127, 127);
+const WHITE: Color = Color(255, 255, 255);
+const RED: Color = Color(214, 39, 40);
+const SILENT_COLOR: Color = Color(31, 119, 180);
+const LOUD_COLOR: Color = Color(255, 127, 14);
+
+/// Draw loudness of the audio, silence threshold and the segments
+/// the video was split into, and save it to given path.
+///
+/// If the path ends with `.png`, PNG image is written. Otherwise, it's SVG.
+pub fn write_plot(
+    path: &Path,
+    title: &str,
+    loudness: &[usize],
+    silent_level: f32,
+    segments: &[Segment],
+    duration: f32,
+) {
+    let shapes = plot_shapes(title, loudness, silent_level, segments, duration);
+    let is_png = path
+        .extension()
+        .map(|extension| extension.eq_ignore_ascii_case("png"))
+        .unwrap_or(false);
+    if is_png {
+        write_png(path, &shapes);
+    } else {
+        fs::write(path, render_svg(&shapes)).expect("Failed to write plot.");
+    }
+}
+
+/// Same as `write_plot`, but return the SVG instead of saving it.
+pub fn svg_plot(
+    title: &str,
+    loudness: &[usize],
+    silent_level: f32,
+    segments: &[Segment],
+    duration: f32,
+) -> String {
+    render_svg(&plot_shapes(
+        title,
+        loudness,
+        silent_level,
+        segments,
+        duration,
+    ))
+}
+
+#[derive(Clone, Copy, PartialEq)]
+struct Color(u8, u8, u8);
+
+enum Anchor {
+    Start,
+    Middle,
+    End,
+}
+
+/// Everything the plot consists of. Coordinates are in pixels,
+/// with origin in the top left corner. Text is positioned by its baseline.
+enum Shape {
+    Rect {
+        x: f32,
+        y: f32,
+        width: f32,
+        height: f32,
+        color: Color,
+        opacity: f32,
+    },
+    Line {
+        from: (f32, f32),
+        to: (f32, f32),
+        color: Color,
+        dashed: bool,
+    },
+    Polyline {
+        points: Vec<(f32, f32)>,
+        color: Color,
+    },
+    Text {
+        x: f32,
+        y: f32,
+        text: String,
+        anchor: Anchor,
+    },
+}
+
+/// Build the plot out of basic shapes, so it can be rendered
+/// to both SVG and bitmap the same way.
+fn plot_shapes(
+    title: &str,
+    loudness: &[usize],
+    silent_level: f32,
+    segments: &[Segment],
+    duration: f32,
+) -> Vec<Shape> {
+    let plot_width = WIDTH - MARGIN_LEFT - MARGIN_RIGHT;
+    let plot_height = HEIGHT - MARGIN_TOP - MARGIN_BOTTOM;
+    let plot_bottom = MARGIN_TOP + plot_height;
+    let duration = if duration > 0.0 { duration } else { 1.0 };
+    let max_loudness = loudness.iter().cloned().max().unwrap_or(0).max(1) as f32;
+    let max_speed = segments
+        .iter()
+        .filter(|s| !s.is_dropped())
+        .map(|s| s.speed)
+        .fold(1.0, f32::max);
+
+    let time_to_x = |seconds: f32| MARGIN_LEFT + seconds / duration * plot_width;
+    let level_to_y = |level: f32| {
+        let db = if level > 0.0 {
+            (20.0 * (level / max_loudness).log10()).max(MIN_DB)
+        } else {
+            MIN_DB
+        };
+        MARGIN_TOP + db / MIN_DB * plot_height
+    };
+
+    let mut shapes: Vec<Shape> = vec![Shape::Rect {
+        x: 0.0,
+        y: 0.0,
+        width: WIDTH,
+        height: HEIGHT,
+        color: WHITE,
+        opacity: 1.0,
+    }];
+
+    // Shade segments. Color depends on the class, and faster
+    // segments are darker. Dropped segments are gray.
+    let mut legend: Vec<(Color, f32, String)> = Vec::new();
+    for segment in segments {
+        let (color, opacity) = segment_style(segment, max_speed);
+        let label = match (segment.silent, segment.is_dropped()) {
+            (true, true) => String::from("silent, dropped"),
+            (false, true) => String::from("loud, dropped"),
+            (true, false) => format!("silent {}x", segment.speed),
+            (false, false) => format!("loud {}x", segment.speed),
+        };
+        if !legend.iter().any(|(_, _, l)| l == &label) {
+            legend.push((color, opacity, label));
+        }
+        shapes.push(Shape::Rect {
+            x: time_to_x(segment.from),
+            y: MARGIN_TOP,
+            width: time_to_x(segment.to) - time_to_x(segment.from),
+            height: plot_height,
+            color,
+            opacity,
+        });
+    }
+
+    // Loudness curve. Long videos have way more audio frames than there are
+    // pixels, so only the loudest frame in each pixel column is drawn.
+    let columns = plot_width as usize;
+    let mut column_max: Vec<Option<usize>> = vec![None; columns + 1];
+    let frame_seconds = duration / loudness.len().max(1) as f32;
+    for (i, level) in loudness.iter().enumerate() {
+        let seconds = (i as f32 + 0.5) * frame_seconds;
+        let column = ((seconds / duration * plot_width) as usize).min(columns);
+        column_max[column] = Some(column_max[column].map_or(*level, |max| max.max(*level)));
+    }
+    shapes.push(Shape::Polyline {
+        points: column_max
+            .iter()
+            .enumerate()
+            .filter_map(|(column, level)| {
+                level.map(|level| (MARGIN_LEFT + column as f32, level_to_y(level as f32)))
+            })
+            .collect(),
+        color: BLACK,
+    });
+
+    // Silence threshold
+    let threshold_y = level_to_y(silent_level);
+    shapes.push(Shape::Line {
+        from: (MARGIN_LEFT, threshold_y),
+        to: (MARGIN_LEFT + plot_width, threshold_y),
+        color: RED,
+        dashed: true,
+    });
+    shapes.push(Shape::Text {
+        x: MARGIN_LEFT + plot_width - 4.0,
+        y: threshold_y - 4.0,
+        text: String::from("silence threshold"),
+        anchor: Anchor::End,
+    });
+
+    // Loudness axis
+    let mut db = 0.0;
+    while db >= MIN_DB {
+        let y = MARGIN_TOP + db / MIN_DB * plot_height;
+        shapes.push(Shape::Line {
+            from: (MARGIN_LEFT - 5.0, y),
+            to: (MARGIN_LEFT, y),
+            color: BLACK,
+            dashed: false,
+        });
+        shapes.push(Shape::Text {
+            x: MARGIN_LEFT - 8.0,
+            y: y + 4.0,
+            text: format!("{} dB", db),
+            anchor: Anchor::End,
+        });
+        db -= 10.0;
+    }
+
+    // Time axis
+    let tick = *TIME_TICKS
+        .iter()
+        .find(|tick| duration / **tick <= MAX_TIME_TICKS)
+        .unwrap_or(&TIME_TICKS[TIME_TICKS.len() - 1]);
+    let mut seconds = 0.0;
+    while seconds <= duration {
+        let x = time_to_x(seconds);
+        shapes.push(Shape::Line {
+            from: (x, plot_bottom),
+            to: (x, plot_bottom + 5.0),
+            color: BLACK,
+            dashed: false,
+        });
+        shapes.push(Shape::Text {
+            x,
+            y: plot_bottom + 20.0,
+            text: format_time(seconds, duration >= 3600.0),
+            anchor: Anchor::Middle,
+        });
+        seconds += tick;
+    }
+
+    // Plot frame
+    shapes.push(Shape::Line {
+        from: (MARGIN_LEFT, MARGIN_TOP),
+        to: (MARGIN_LEFT, plot_bottom),
+        color: BLACK,
+        dashed: false,
+    });
+    shapes.push(Shape::Line {
+        from: (MARGIN_LEFT, plot_bottom),
+        to: (MARGIN_LEFT + plot_width, plot_bottom),
+        color: BLACK,
+        dashed: false,
+    });
+
+    // Title and legend
+    shapes.push(Shape::Text {
+        x: MARGIN_LEFT,
+        y: 20.0,
+        text: String::from(title),
+        anchor: Anchor::Start,
+    });
+    let mut x = MARGIN_LEFT;
+    for (color, opacity, label) in legend {
+        shapes.push(Shape::Rect {
+            x,
+            y: 30.0,
+            width: 12.0,
+            height: 12.0,
+            color,
+            opacity,
+        });
+        shapes.push(Shape::Text {
+            x: x + 18.0,
+            y: 41.0,
+            text: label.clone(),
+            anchor: Anchor::Start,
+        });
+        x += 30.0 + label.len() as f32 * 8.0;
+    }
+
+    shapes
+}
+
+fn segment_style(segment: &Segment, max_speed: f32) -> (Color, f32) {
+    if segment.is_dropped() {
+        return (GRAY, 0.45);
+    }
+    let color = if segment.silent {
+        SILENT_COLOR
+    } else {
+        LOUD_COLOR
+    };
+    // Speed 1 is barely visible, the fastest segments are clearly visible
+    let opacity = if max_speed > 1.0 {
+        0.1 + 0.3 * (segment.speed.max(1.0).ln() / max_speed.ln())
+    } else {
+        0.1
+    };
+    (color, opacity)
+}
+
+fn format_time(seconds: f32, with_hours: bool) -> String {
+    let seconds = seconds as usize;
+    if with_hours {
+        format!(
+            "{}:{:02}:{:02}",
+            seconds / 3600,
+            seconds / 60 % 60,
+            seconds % 60
+        )
+    } else {
+        format!("{}:{:02}", seconds / 60, seconds % 60)
+    }
+}
+
+fn color_to_hex(color: Color) -> String {
+    format!("#{:02x}{:02x}{:02x}", color.0, color.1, color.2)
+}
+
+fn escape_xml(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+fn render_svg(shapes: &[Shape]) -> String {
+    let mut svg = format!(
+        "<svg xmlns=\"http://www.w3.org/2000/svg\" width=\"{0}\" height=\"{1}\" viewBox=\"0 0 {0} {1}\" font-family=\"sans-serif\" font-size=\"12\">\n",
+        WIDTH, HEIGHT
+    );
+    for shape in shapes {
+        match shape {
+            Shape::Rect {
+                x,
+                y,
+                width,
+                height,
+                color,
+                opacity,
+            } => svg.push_str(&format!(
+                "<rect x=\"{}\" y=\"{}\" width=\"{}\" height=\"{}\" fill=\"{}\" fill-opacity=\"{}\"/>\n",
+                x,
+                y,
+                width,
+                height,
+                color_to_hex(*color),
+                opacity
+            )),
+            Shape::Line {
+                from,
+                to,
+                color,
+                dashed,
+            } => svg.push_str(&format!(
+                "<line x1=\"{}\" y1=\"{}\" x2=\"{}\" y2=\"{}\" stroke=\"{}\"{}/>\n",
+                from.0,
+                from.1,
+                to.0,
+                to.1,
+                color_to_hex(*color),
+                if *dashed {
+                    " stroke-dasharray=\"6,4\""
+                } else {
+                    ""
+                }
+            )),
+            Shape::Polyline { points, color } => svg.push_str(&format!(
+                "<polyline fill=\"none\" stroke=\"{}\" stroke-width=\"1\" points=\"{}\"/>\n",
+                color_to_hex(*color),
+                points
+                    .iter()
+                    .map(|(x, y)| format!("{:.1},{:.1}", x, y))
+                    .collect::<Vec<String>>()
+                    .join(" ")
+            )),
+            Shape::Text { x, y, text, anchor } => svg.push_str(&format!(
+                "<text x=\"{}\" y=\"{}\" text-anchor=\"{}\">{}</text>\n",
+                x,
+                y,
+                match anchor {
+                    Anchor::Start => "start",
+                    Anchor::Middle => "middle",
+                    Anchor::End => "end",
+                },
+                escape_xml(text)
+            )),
+        }
+    }
+    svg.push_str("</svg>\n");
+    svg
+}
+
+/// Simple RGB bitmap the plot is rasterized to, when writing PNG.
+struct Canvas {
+    width: usize,
+    height: usize,
+    pixels: Vec<u8>,
+}
+
+/// Size of one pixel of the bitmap font.
+const FONT_SCALE: usize = 2;
+
+impl Canvas {
+    fn new(width: usize, height: usize) -> Canvas {
+        Canvas {
+            width,
+            height,
+            pixels: vec![255; width * height * 3],
+        }
+    }
+
+    fn blend(&mut self, x: isize, y: isize, color: Color, opacity: f32) {
+        if x < 0 || y < 0 || x as usize >= self.width || y as usize >= self.height {
+            return;
+        }
+        let index = (y as usize * self.width + x as usize) * 3;
+        for (channel, value) in [color.0, color.1, color.2].iter().enumerate() {
+            let old = self.pixels[index + channel] as f32;
+            self.pixels[index + channel] = (old + (*value as f32 - old) * opacity) as u8;
+        }
+    }
+
+    fn fill_rect(&mut self, x: f32, y: f32, width: f32, height: f32, color: Color, opacity: f32) {
+        for py in y.round() as isize..(y + height).round() as isize {
+            // Make sure even very short segments are visible
+            for px in x.round() as isize..((x + width).round() as isize).max(x.round() as isize + 1)
+            {
+                self.blend(px, py, color, opacity);
+            }
+        }
+    }
+
+    fn draw_line(&mut self, from: (f32, f32), to: (f32, f32), color: Color, dashed: bool) {
+        let steps = (to.0 - from.0).abs().max((to.1 - from.1).abs()).ceil() as usize;
+        for step in 0..=steps {
+            // 6 pixels on, 4 pixels off, same as in the SVG
+            if dashed && step % 10 >= 6 {
+                continue;
+            }
+            let t = if steps > 0 {
+                step as f32 / steps as f32
+            } else {
+                0.0
+            };
+            self.blend(
+                (from.0 + (to.0 - from.0) * t).round() as isize,
+                (from.1 + (to.1 - from.1) * t).round() as isize,
+                color,
+                1.0,
+            );
+        }
+    }
+
+    fn draw_text(&mut self, x: f32, y: f32, text: &str, anchor: &Anchor) {
+        let advance = 4 * FONT_SCALE;
+        let text_width = (text.chars().count() * advance) as f32;
+        let left = match anchor {
+            Anchor::Start => x,
+            Anchor::Middle => x - text_width / 2.0,
+            Anchor::End => x - text_width,
+        } as isize;
+        let top = y as isize - (5 * FONT_SCALE) as isize;
+        for (i, character) in text.chars().enumerate() {
+            let rows = match glyph(character) {
+                Some(rows) => rows,
+                None => continue,
+            };
+            for (row, bits) in rows.iter().enumerate() {
+                for column in 0..3 {
+                    if bits & (0b100 >> column) == 0 {
+                        continue;
+                    }
+                    for dy in 0..FONT_SCALE {
+                        for dx in 0..FONT_SCALE {
+                            self.blend(
+                                left + (i * advance + column * FONT_SCALE + dx) as isize,
+                                top + (row * FONT_SCALE + dy) as isize,
+                                BLACK,
+                                1.0,
+                            );
+                        }
+                    }
+                }
+            }
+        }
+    }
+}
+
+fn write_png(path: &Path, shapes: &[Shape]) {
+    let mut canvas = Canvas::new(WIDTH as usize, HEIGHT as usize);
+    for shape in shapes {
+        match shape {
+            Shape::Rect {
+                x,
+                y,
+                width,
+                height,
+                color,
+                opacity,
+            } => canvas.fill_rect(*x, *y, *width, *height, *color, *opacity),
+            Shape::Line {
+                from,
+                to,
+                color,
+                dashed,
+            } => canvas.draw_line(*from, *to, *color, *dashed),
+            Shape::Polyline { points, color } => {
+                for pair in points.windows(2) {
+                    canvas.draw_line(pair[0], pair[1], *color, false);
+                }
+            }
+            Shape::Text { x, y, text, anchor } => canvas.draw_text(*x, *y, text, anchor),
+        }
+    }
+
+    let file = fs::File::create(path).expect("Failed to create plot file.");
+    let mut encoder = png::Encoder::new(
+        BufWriter::new(file),
+        canvas.width as u32,
+        canvas.height as u32,
+    );
+    encoder.set_color(png::ColorType::RGB);
+    encoder.set_depth(png::BitDepth::Eight);
+    encoder
+        .write_header()
+        .expect("Failed to write plot.")
+        .write_image_data(&canvas.pixels)
+        .expect("Failed to write plot.");
+}
+
+/// Tiny 3x5 bitmap font, so we can label bitmap plots without
+/// depending on fonts installed in the system. Each row is 3 bits wide.
+/// Lowercase letters are drawn as uppercase.
+fn glyph(character: char) -> Option<[u8; 5]> {
+    let rows = match character.to_ascii_uppercase() {
+        '0' => [0b111, 0b101, 0b101, 0b101, 0b111],
+        '1' => [0b010, 0b110, 0b010, 0b010, 0b111],
+        '2' => [0b111, 0b001, 0b111, 0b100, 0b111],
+        '3' => [0b111, 0b001, 0b111, 0b001, 0b111],
+        '4' => [0b101, 0b101, 0b111, 0b001, 0b001],
+        '5' => [0b111, 0b100, 0b111, 0b001, 0b111],
+        '6' => [0b111, 0b100, 0b111, 0b101, 0b111],
+        '7' => [0b111, 0b001, 0b001, 0b001, 0b001],
+        '8' => [0b111, 0b101, 0b111, 0b101, 0b111],
+        '9' => [0b111, 0b101, 0b111, 0b001, 0b111],
+        ':' => [0b000, 0b010, 0b000, 0b010, 0b000],
+        '.' => [0b000, 0b000, 0b000, 0b000, 0b010],
+        ',' => [0b000, 0b000, 0b000, 0b010, 0b100],
+        '-' => [0b000, 0b000, 0b111, 0b000, 0b000],
+        '_' => [0b000, 0b000, 0b000, 0b000, 0b111],
+        '%' => [0b101, 0b001, 0b010, 0b100, 0b101],
+        '(' => [0b010, 0b100, 0b100, 0b100, 0b010],
+        ')' => [0b010, 0b001, 0b001, 0b001, 0b010],
+        '/' => [0b001, 0b001, 0b010, 0b100, 0b100],
+        'A' => [0b010, 0b101, 0b111, 0b101, 0b101],
+        'B' => [0b110, 0b101, 0b110, 0b101, 0b110],
+        'C' => [0b011, 0b100, 0b100, 0b100, 0b011],
+        'D' => [0b110, 0b101, 0b101, 0b101, 0b110],
+        'E' => [0b111, 0b100, 0b110, 0b100, 0b111],
+        'F' => [0b111, 0b100, 0b110, 0b100, 0b100],
+        'G' => [0b011, 0b100, 0b101, 0b101, 0b011],
+        'H' => [0b101, 0b101, 0b111, 0b101, 0b101],
+        'I' => [0b111, 0b010, 0b010, 0b010, 0b111],
+        'J' => [0b001, 0b001, 0b001, 0b101, 0b010],
+        'K' => [0b101, 0b101, 0b110, 0b101, 0b101],
+        'L' => [0b100, 0b100, 0b100, 0b100, 0b111],
+        'M' => [0b101, 0b111, 0b111, 0b101, 0b101],
+        'N' => [0b110, 0b101, 0b101, 0b101, 0b101],
+        'O' => [0b010, 0b101, 0b101, 0b101, 0b010],
+        'P' => [0b110, 0b101, 0b110, 0b100, 0b100],
+        'Q' => [0b010, 0b101, 0b101, 0b110, 0b011],
+        'R' => [0b110, 0b101, 0b110, 0b101, 0b101],
+        'S' => [0b011, 0b100, 0b010, 0b001, 0b110],
+        'T' => [0b111, 0b010, 0b010, 0b010, 0b010],
+        'U' => [0b101, 0b101, 0b101, 0b101, 0b111],
+        'V' => [0b101, 0b101, 0b101, 0b101, 0b010],
+        'W' => [0b101, 0b101, 0b111, 0b111, 0b101],
+        'X' => [0b101, 0b101, 0b010, 0b101, 0b101],
+        'Y' => [0b101, 0b101, 0b010, 0b010, 0b010],
+        'Z' => [0b111, 0b001, 0b010, 0b100, 0b111],
+        _ => return None,
+    };
+    Some(rows)
+}
@@ -0,0 +1,119 @@
+use serde::Deserialize;
+
+use crate::config::Config;
+use crate::Cli;
+
+/// Names of presets that are always available.
+pub const BUILTIN_PRESETS: [&str; 4] = ["archive", "mobile", "podcast", "lossless-audio"];
+
+/// Named set of output settings. Everything that is not set
+/// is left as it would be without the preset.
+///
+/// User-defined presets are read from the `[presets.<name>]` tables
+/// in the configuration file, with keys named as the command line options.
+#[derive(Deserialize, Default, Clone)]
+#[serde(deny_unknown_fields, rename_all = "kebab-case")]
+pub struct Preset {
+    pub format: Option<String>,
+    pub video_codec: Option<String>,
+    pub audio_codec: Option<String>,
+    pub crf: Option<u8>,
+    pub bitrate: Option<String>,
+    pub audio_bitrate: Option<String>,
+    pub encoder_preset: Option<String>,
+    pub max_height: Option<u32>,
+    #[serde(default)]
+    pub audio: bool,
+}
+
+impl Preset {
+    /// Fill in options from this preset. Options that were set on the
+    /// command line are kept.
+    ///
+    /// Container is taken from the preset only if user neither
+    /// chose one, nor named the output with an extension.
+    pub fn apply(&self, args: &mut Cli) {
+        if args.format.is_none() && args.output.extension().is_none() {
+            args.format = self.format.clone();
+        }
+        if args.video_codec.is_none() {
+            args.video_codec = self.video_codec.clone();
+        }
+        if args.audio_codec.is_none() {
+            args.audio_codec = self.audio_codec.clone();
+        }
+        // CRF and bitrate are mutually exclusive, if user set
+        // either of them, ignore both from the preset
+        if args.crf.is_none() && args.bitrate.is_none() {
+            args.crf = self.crf;
+            args.bitrate = self.bitrate.clone();
+        }
+        if args.audio_bitrate.is_none() {
+            args.audio_bitrate = self.audio_bitrate.clone();
+        }
+        if args.encoder_preset.is_none() {
+            args.encoder_preset = self.encoder_preset.clone();
+        }
+        if args.max_height.is_none() {
+            args.max_height = self.max_height;
+        }
+        args.audio = args.audio || self.audio;
+    }
+}
+
+/// Find preset by name. Presets from the configuration file
+/// take precedence over the built-in ones.
+pub fn find(name: &str, config: &Config) -> Result<Preset, String> {
+    if let Some(preset) = config.presets.get(name) {
+        return Ok(preset.clone());
+    }
+    builtin(name).ok_or_else(|| {
+        let mut names: Vec<&str> = BUILTIN_PRESETS.to_vec();
+        names.extend(config.presets.keys().map(|name| name.as_str()));
+        format!(
+            "Unknown preset \"{}\". Available presets are: {}.",
+            name,
+            names.join(", ")
+        )
+    })
+}
+
+fn builtin(name: &str) -> Option<Preset> {
+    let preset = match name {
+        // High quality, for keeping the video around
+        "archive" => Preset {
+            format: Some(String::from("matroska")),
+            video_codec: Some(String::from("libx264")),
+            audio_codec: Some(String::from("aac")),
+            crf: Some(18),
+            audio_bitrate: Some(String::from("192k")),
+            encoder_preset: Some(String::from("slow")),
+            ..Preset::default()
+        },
+        // Small file that plays everywhere
+        "mobile" => Preset {
+            format: Some(String::from("mp4")),
+            video_codec: Some(String::from("libx264")),
+            audio_codec: Some(String::from("aac")),
+            crf: Some(28),
+            audio_bitrate: Some(String::from("96k")),
+            max_height: Some(720),
+            ..Preset::default()
+        },
+        "podcast" => Preset {
+            format: Some(String::from("opus")),
+            audio_codec: Some(String::from("libopus")),
+            audio_bitrate: Some(String::from("64k")),
+            audio: true,
+            ..Preset::default()
+        },
+        "lossless-audio" => Preset {
+            format: Some(String::from("flac")),
+            audio_codec: Some(String::from("flac")),
+            audio: true,
+            ..Preset::default()
+        },
+        _ => return None,
+    };
+    Some(preset)
+}
@@ -0,0 +1,239 @@
+use serde::Serialize;
+
+use std::fs;
+use std::path::Path;
+
+use crate::plot;
+use crate::stats::Stats;
+
+/// Everything the report shows, apart from the plot. This is embedded
+/// in the report as JSON, and the page is built from it.
+#[derive(Serialize)]
+struct ReportData<'a> {
+    input: &'a str,
+    parameters: Vec<Parameter<'a>>,
+    stats: &'a Stats<'a>,
+}
+
+#[derive(Serialize)]
+struct Parameter<'a> {
+    name: &'a str,
+    value: String,
+}
+
+/// Write single HTML file with loudness plot, segment table, stats
+/// and parameters that were used. The report contains video player
+/// pointed to the input file, and clicking a segment seeks to it.
+pub fn write_report(
+    path: &Path,
+    input: &Path,
+    parameters: &[(&str, String)],
+    stats: &Stats,
+    loudness: &[usize],
+    silent_level: f32,
+    duration: f32,
+) {
+    let title = input.file_name().unwrap().to_str().unwrap();
+    let data = ReportData {
+        input: title,
+        parameters: parameters
+            .iter()
+            .map(|(name, value)| Parameter {
+                name,
+                value: value.clone(),
+            })
+            .collect(),
+        stats,
+    };
+    // Make sure the data can't end the script tag it's embedded in
+    let data_json = serde_json::to_string(&data)
+        .expect("Failed to serialize report data.")
+        .replace("</", "<\\/");
+    let svg = plot::svg_plot(title, loudness, silent_level, stats.segments, duration);
+
+    let html = REPORT_TEMPLATE
+        .replace("{{title}}", &escape_html(title))
+        .replace("{{video_src}}", &escape_html(&video_src(path, input)))
+        .replace("{{plot}}", &svg)
+        .replace("{{plot_width}}", &plot::WIDTH.to_string())
+        .replace("{{plot_left}}", &plot::MARGIN_LEFT.to_string())
+        .replace("{{plot_right}}", &plot::MARGIN_RIGHT.to_string())
+        .replace("{{duration}}", &duration.to_string())
+        .replace("{{data}}", &data_json);
+    fs::write(path, html).expect("Failed to write report.");
+}
+
+/// Path to the video, as seen from the report. If the report is next to
+/// the video, only the filename is used, so both can be moved elsewhere together.
+fn video_src(report_path: &Path, input: &Path) -> String {
+    let input = fs::canonicalize(input).expect("Failed to get absolute path of input file.");
+    let report_directory = report_path
+        .parent()
+        .filter(|parent| !parent.as_os_str().is_empty())
+        .unwrap_or_else(|| Path::new("."));
+    let report_directory = fs::canonicalize(report_directory)
+        .expect("Failed to get absolute path of report directory.");
+    if input.parent() == Some(report_directory.as_path()) {
+        percent_encode(input.file_name().unwrap().to_str().unwrap())
+    } else {
+        format!("file://{}", percent_encode(input.to_str().unwrap()))
+    }
+}
+
+fn percent_encode(path: &str) -> String {
+    let mut encoded = String::new();
+    for byte in path.bytes() {
+        match byte {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'_' | b'.' | b'~' | b'/' => {
+                encoded.push(byte as char)
+            }
+            _ => encoded.push_str(&format!("%{:02X}", byte)),
+        }
+    }
+    encoded
+}
+
+fn escape_html(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+const REPORT_TEMPLATE: &str = r##"<!DOCTYPE html>
+<html>
+<head>
+<meta charset="utf-8">
+<title>{{title}} - video summarizer report</title>
+<style>
+body { font-family: sans-serif; margin: 2em auto; max-width: 1200px; color: #222; }
+video { width: 100%; max-height: 60vh; background: black; }
+#plot svg { width: 100%; height: auto; cursor: pointer; }
+table { border-collapse: collapse; margin: 1em 0; }
+th, td { padding: 0.2em 0.8em; text-align: right; border-bottom: 1px solid #ddd; }
+th:first-child, td:first-child { text-align: left; }
+#segments tbody tr { cursor: pointer; }
+#segments tbody tr:hover { background: #eef; }
+#segments tbody tr.current { background: #fde9c9; }
+.silent { color: #1f77b4; }
+.loud { color: #d96a00; }
+#segments-wrapper { max-height: 30em; overflow-y: auto; }
+</style>
+</head>
+<body>
+<h1>{{title}}</h1>
+<video id="video" src="{{video_src}}" controls preload="metadata"></video>
+<div id="plot">{{plot}}</div>
+
+<h2>Statistics</h2>
+<p id="summary"></p>
+<table id="classes">
+<thead><tr><th>class</th><th>count</th><th>total (s)</th><th>mean (s)</th><th>summarized (s)</th></tr></thead>
+<tbody></tbody>
+</table>
+
+<h2>Parameters</h2>
+<table id="parameters"><tbody></tbody></table>
+
+<h2>Segments</h2>
+<p>Click a segment to play it.</p>
+<div id="segments-wrapper">
+<table id="segments">
+<thead><tr><th>#</th><th>class</th><th>from</th><th>to</th><th>length (s)</th><th>speed</th><th>summarized (s)</th></tr></thead>
+<tbody></tbody>
+</table>
+</div>
+
+<script>
+const report = {{data}};
+const PLOT_WIDTH = {{plot_width}};
+const PLOT_LEFT = {{plot_left}};
+const PLOT_RIGHT = {{plot_right}};
+const DURATION = {{duration}};
+
+const video = document.getElementById("video");
+const segments = report.stats.segments;
+
+function formatTime(seconds) {
+    const minutes = Math.floor(seconds / 60);
+    const rest = (seconds - minutes * 60).toFixed(2).padStart(5, "0");
+    return minutes + ":" + rest;
+}
+
+function addRow(table, cells) {
+    const row = table.querySelector("tbody").insertRow();
+    for (const cell of cells) {
+        row.insertCell().textContent = cell;
+    }
+    return row;
+}
+
+function seek(seconds) {
+    video.currentTime = seconds;
+    video.play();
+}
+
+const stats = report.stats;
+document.getElementById("summary").textContent =
+    stats.silent_percentage.toFixed(2) + "% of video is silent. Video is " +
+    (stats.duration / 60).toFixed(1) + " minutes long, summarized video is " +
+    (stats.summarized_duration / 60).toFixed(1) + " minutes long. Time saved is " +
+    (stats.time_saved / 60).toFixed(1) + " minutes.";
+
+for (const c of stats.classes) {
+    addRow(document.getElementById("classes"), [
+        c.class, c.count, c.total_length.toFixed(1), c.mean_length.toFixed(2), c.summarized_length.toFixed(1),
+    ]);
+}
+
+for (const p of report.parameters) {
+    addRow(document.getElementById("parameters"), [p.name, p.value]);
+}
+
+const rows = segments.map((s, i) => {
+    const dropped = s.speed >= 100;
+    const row = addRow(document.getElementById("segments"), [
+        i + 1,
+        s.silent ? "silent" : "loud",
+        formatTime(s.from),
+        formatTime(s.to),
+        (s.to - s.from).toFixed(2),
+        dropped ? "dropped" : s.speed + "x",
+        dropped ? "0.00" : ((s.to - s.from) / s.speed).toFixed(2),
+    ]);
+    row.className = s.silent ? "silent" : "loud";
+    row.addEventListener("click", () => seek(s.from));
+    return row;
+});
+
+// Clicking the plot seeks to the segment under the cursor
+document.querySelector("#plot svg").addEventListener("click", (event) => {
+    const box = event.currentTarget.getBoundingClientRect();
+    const x = (event.clientX - box.left) / box.width * PLOT_WIDTH;
+    const seconds = (x - PLOT_LEFT) / (PLOT_WIDTH - PLOT_LEFT - PLOT_RIGHT) * DURATION;
+    const segment = segments.find((s) => s.from <= seconds && seconds < s.to);
+    if (segment) {
+        seek(segment.from);
+    }
+});
+
+// Highlight segment that is being played
+let current = null;
+video.addEventListener("timeupdate", () => {
+    const index = segments.findIndex((s) => s.from <= video.currentTime && video.currentTime < s.to);
+    const row = index >= 0 ? rows[index] : null;
+    if (row === current) {
+        return;
+    }
+    if (current) {
+        current.classList.remove("current");
+    }
+    if (row) {
+        row.classList.add("current");
+    }
+    current = row;
+});
+</script>
+</body>
+</html>
+"##;
@@ -0,0 +1,496 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use crate::output::OutputFormat;
+use crate::parallel;
+use crate::tempdir::TempDir;
+use crate::timeline::Segment;
+use crate::Cli;
+
+/// Part of the output, that is either copied from the source video
+/// as it is, or re-encoded, because it doesn't start at a keyframe.
+/// Times are in seconds, as `f64`, so a piece that is copied starts
+/// exactly at its keyframe even in long videos.
+#[derive(Debug, PartialEq)]
+struct Piece {
+    from: f64,
+    to: f64,
+    copy: bool,
+}
+
+/// Codecs of the source video, and encoders that produce them.
+struct SourceCodecs {
+    video_encoder: &'static str,
+    pixel_format: String,
+    audio_encoder: Option<&'static str>,
+}
+
+/// Whether the video can be rendered by cutting only, that is segments
+/// of every speed are either played at normal speed, or dropped.
+pub fn is_cut_only(speeds: &[f32]) -> bool {
+    speeds.iter().all(|speed| *speed >= 100.0 || *speed == 1.0)
+}
+
+/// Render video that only has parts of the source dropped, without
+/// re-encoding it whole (so called smart rendering).
+///
+/// Kept parts of the video are copied as they are, from keyframe to keyframe.
+/// Cut that is at most `--keyframe-tolerance` seconds from a keyframe is moved
+/// to it. Otherwise, the few frames between the cut and the nearest keyframe are
+/// re-encoded with the source codecs, so they can be joined with the copied parts.
+pub fn render_cut_only(
+    args: &Cli,
+    input: &Path,
+    output: &Path,
+    segments: &[Segment],
+    output_format: &OutputFormat,
+) -> Result<(), String> {
+    let codecs = probe_codecs(input)?;
+    let keyframes = probe_keyframes(input);
+    if keyframes.is_empty() {
+        return Err(String::from(
+            "Failed to find keyframes of the source video, it cannot be cut without re-encoding.",
+        ));
+    }
+
+    let mut pieces: Vec<Piece> = Vec::new();
+    for (from, to) in kept_intervals(segments) {
+        split_interval(
+            from,
+            to,
+            &keyframes,
+            args.keyframe_tolerance as f64,
+            &mut pieces,
+        );
+    }
+    if !args.quiet {
+        let copied: f64 = pieces
+            .iter()
+            .filter(|p| p.copy)
+            .map(|p| p.to - p.from)
+            .sum();
+        let total: f64 = pieces.iter().map(|p| p.to - p.from).sum();
+        eprintln!(
+            "Copying {:.1} of {:.1} seconds, re-encoding the rest",
+            copied, total
+        );
+    }
+
+    let tempdir = TempDir::new();
+    let pieces_done = AtomicUsize::new(0);
+    let piece_paths = parallel::map(&pieces, args.jobs(), |index, piece| {
+        // MPEG-TS keeps codec headers in the stream, so copied and
+        // re-encoded pieces can be joined
+        let piece_path = tempdir.path().join(format!("piece-{:06}.ts", index));
+        render_piece(input, piece, &codecs, &piece_path)?;
+        let done = pieces_done.fetch_add(1, Ordering::SeqCst) + 1;
+        if !args.quiet {
+            eprintln!("{}%", done as f32 / pieces.len() as f32 * 100.0);
+        }
+        Ok(piece_path)
+    })
+    .into_iter()
+    .collect::<Result<Vec<PathBuf>, String>>()?;
+
+    let list_path = tempdir.path().join("pieces.txt");
+    fs::write(
+        &list_path,
+        piece_paths
+            .iter()
+            .map(|path| format!("file '{}'", path.to_str().unwrap()))
+            .collect::<Vec<String>>()
+            .join("\n"),
+    )
+    .expect("Failed to write list of pieces.");
+    let status = Command::new("ffmpeg")
+        .args([
+            "-f",
+            "concat",
+            "-safe",
+            "0",
+            "-i",
+            list_path.to_str().unwrap(),
+            "-c",
+            "copy",
+        ])
+        .args(output_format.args())
+        .arg(output.to_str().unwrap())
+        .stdin(Stdio::null())
+        .stdout(Stdio::inherit())
+        .stderr(Stdio::null())
+        .spawn()
+        .expect("Failed to run pieces concatenate process")
+        .wait()
+        .expect("Failed to concatenate pieces.");
+    if !status.success() {
+        return Err(String::from("Failed to concatenate pieces of the video."));
+    }
+    Ok(())
+}
+
+/// Join neighbouring segments that are kept into intervals (in seconds).
+fn kept_intervals(segments: &[Segment]) -> Vec<(f64, f64)> {
+    let mut intervals: Vec<(f64, f64)> = Vec::new();
+    for segment in segments.iter().filter(|s| !s.is_dropped()) {
+        let (from, to) = (segment.from as f64, segment.to as f64);
+        match intervals.last_mut() {
+            Some(last) if (from - last.1).abs() < 0.001 => last.1 = to,
+            _ => intervals.push((from, to)),
+        }
+    }
+    intervals
+}
+
+/// Split kept interval into pieces that are copied from keyframe to
+/// keyframe, and pieces at the edges that are re-encoded.
+fn split_interval(from: f64, to: f64, keyframes: &[f64], tolerance: f64, pieces: &mut Vec<Piece>) {
+    let nearest = |time: f64| {
+        keyframes
+            .iter()
+            .cloned()
+            .min_by(|a, b| (a - time).abs().partial_cmp(&(b - time).abs()).unwrap())
+            .filter(|keyframe| (keyframe - time).abs() <= tolerance)
+    };
+    // Snap cuts that are close enough to keyframes
+    let original_from = from;
+    let (from, copy_from) = match nearest(from) {
+        Some(keyframe) => (keyframe, keyframe),
+        None => (
+            from,
+            *keyframes
+                .iter()
+                .find(|keyframe| **keyframe > from)
+                .unwrap_or(&to),
+        ),
+    };
+    let (snapped_to, copy_to) = match nearest(to) {
+        Some(keyframe) => (keyframe, keyframe),
+        None => (
+            to,
+            *keyframes
+                .iter()
+                .rev()
+                .find(|keyframe| **keyframe < to)
+                .unwrap_or(&from),
+        ),
+    };
+
+    // Both ends of a short interval may snap to the same keyframe,
+    // keep all of it then
+    if snapped_to <= from {
+        if to > original_from {
+            pieces.push(Piece {
+                from: original_from,
+                to,
+                copy: false,
+            });
+        }
+        return;
+    }
+    let to = snapped_to;
+
+    if copy_from >= copy_to {
+        // No keyframe in the interval, re-encode it whole
+        if to > from {
+            pieces.push(Piece {
+                from,
+                to,
+                copy: false,
+            });
+        }
+        return;
+    }
+    if copy_from > from {
+        pieces.push(Piece {
+            from,
+            to: copy_from,
+            copy: false,
+        });
+    }
+    pieces.push(Piece {
+        from: copy_from,
+        to: copy_to,
+        copy: true,
+    });
+    if to > copy_to {
+        pieces.push(Piece {
+            from: copy_to,
+            to,
+            copy: false,
+        });
+    }
+}
+
+fn render_piece(
+    input: &Path,
+    piece: &Piece,
+    codecs: &SourceCodecs,
+    piece_path: &Path,
+) -> Result<(), String> {
+    let mut args: Vec<String> = vec![
+        String::from("-y"),
+        String::from("-ss"),
+        piece.from.to_string(),
+        String::from("-i"),
+        input.to_str().unwrap().to_string(),
+        String::from("-t"),
+        (piece.to - piece.from).to_string(),
+        String::from("-map"),
+        String::from("0:v:0"),
+        String::from("-map"),
+        String::from("0:a:0?"),
+    ];
+    if piece.copy {
+        args.extend(vec![String::from("-c"), String::from("copy")]);
+    } else {
+        args.extend(vec![
+            String::from("-c:v"),
+            codecs.video_encoder.to_string(),
+            String::from("-pix_fmt"),
+            codecs.pixel_format.clone(),
+        ]);
+        if let Some(audio_encoder) = codecs.audio_encoder {
+            args.extend(vec![String::from("-c:a"), audio_encoder.to_string()]);
+        }
+    }
+    args.extend(vec![
+        String::from("-avoid_negative_ts"),
+        String::from("make_zero"),
+        String::from("-f"),
+        String::from("mpegts"),
+        piece_path.to_str().unwrap().to_string(),
+    ]);
+
+    let status = Command::new("ffmpeg")
+        .args(&args)
+        .stdin(Stdio::null())
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .status()
+        .expect("Failed to spawn ffmpeg to cut the video.");
+    if !status.success() {
+        return Err(format!(
+            "Failed to cut the video from {} to {} seconds.",
+            piece.from, piece.to
+        ));
+    }
+    Ok(())
+}
+
+/// Times of keyframes of the first video stream, in seconds.
+fn probe_keyframes(input: &Path) -> Vec<f64> {
+    // Reading packets is much faster than decoding the frames
+    let packets = Command::new("ffprobe")
+        .args([
+            "-v",
+            "error",
+            "-select_streams",
+            "v:0",
+            "-show_entries",
+            "packet=pts_time,flags",
+            "-of",
+            "csv=print_section=0",
+            input.to_str().unwrap(),
+        ])
+        .stdin(Stdio::null())
+        .stderr(Stdio::null())
+        .stdout(Stdio::piped())
+        .output()
+        .expect("Failed to get keyframes via ffprobe.");
+    // Expected format: 12.345000,K__
+    let mut keyframes: Vec<f64> = String::from_utf8_lossy(&packets.stdout)
+        .lines()
+        .filter_map(|line| {
+            let mut fields = line.split(',');
+            let time = fields.next()?.trim().parse::<f64>().ok()?;
+            let flags = fields.next()?;
+            if flags.contains('K') {
+                Some(time)
+            } else {
+                None
+            }
+        })
+        .collect();
+    keyframes.sort_by(|a, b| a.partial_cmp(b).unwrap());
+    keyframes
+}
+
+/// Find out what the source video is encoded with, so the re-encoded pieces
+/// can be encoded the same way.
+fn probe_codecs(input: &Path) -> Result<SourceCodecs, String> {
+    let video = probe_stream(input, "v:0", "codec_name,pix_fmt");
+    let video_codec = video.first().cloned().unwrap_or_default();
+    let video_encoder = encoder_for(&video_codec).ok_or_else(|| {
+        format!(
+            "Video encoded with \"{}\" cannot be cut without re-encoding. Render it without the --smart-cut option.",
+            video_codec
+        )
+    })?;
+    let audio = probe_stream(input, "a:0", "codec_name");
+    let audio_encoder = match audio.first() {
+        Some(audio_codec) => Some(encoder_for(audio_codec).ok_or_else(|| {
+            format!(
+                "Audio encoded with \"{}\" cannot be cut without re-encoding. Render it without the --smart-cut option.",
+                audio_codec
+            )
+        })?),
+        None => None,
+    };
+    Ok(SourceCodecs {
+        video_encoder,
+        pixel_format: video
+            .get(1)
+            .cloned()
+            .unwrap_or_else(|| String::from("yuv420p")),
+        audio_encoder,
+    })
+}
+
+/// Values of given entries of a stream, in the order ffprobe prints them.
+fn probe_stream(input: &Path, stream: &str, entries: &str) -> Vec<String> {
+    let probe = Command::new("ffprobe")
+        .args([
+            "-v",
+            "error",
+            "-select_streams",
+            stream,
+            "-show_entries",
+            &format!("stream={}", entries),
+            "-of",
+            "default=noprint_wrappers=1:nokey=1",
+            input.to_str().unwrap(),
+        ])
+        .stdin(Stdio::null())
+        .stderr(Stdio::null())
+        .stdout(Stdio::piped())
+        .output()
+        .expect("Failed to get codecs via ffprobe.");
+    String::from_utf8_lossy(&probe.stdout)
+        .lines()
+        .map(|line| line.trim().to_string())
+        .filter(|line| !line.is_empty())
+        .collect()
+}
+
+/// ffmpeg encoder that produces given codec. Only codecs that
+/// MPEG-TS can carry are supported, so VP8, VP9 and Vorbis are not.
+fn encoder_for(codec: &str) -> Option<&'static str> {
+    let encoder = match codec {
+        "h264" => "libx264",
+        "hevc" => "libx265",
+        "mpeg4" => "mpeg4",
+        "mpeg2video" => "mpeg2video",
+        "mpeg1video" => "mpeg1video",
+        "aac" => "aac",
+        "mp3" => "libmp3lame",
+        "mp2" => "mp2",
+        "opus" => "libopus",
+        "ac3" => "ac3",
+        _ => return None,
+    };
+    Some(encoder)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn split(from: f64, to: f64, keyframes: &[f64]) -> Vec<Piece> {
+        let mut pieces = Vec::new();
+        split_interval(from, to, keyframes, 0.5, &mut pieces);
+        pieces
+    }
+
+    fn piece(from: f64, to: f64, copy: bool) -> Piece {
+        Piece { from, to, copy }
+    }
+
+    const KEYFRAMES: [f64; 4] = [0.0, 10.0, 20.0, 30.0];
+
+    #[test]
+    fn cuts_snap_to_keyframes() {
+        assert_eq!(split(9.8, 20.3, &KEYFRAMES), vec![piece(10.0, 20.0, true)]);
+    }
+
+    #[test]
+    fn edges_are_reencoded() {
+        assert_eq!(
+            split(5.0, 25.0, &KEYFRAMES),
+            vec![
+                piece(5.0, 10.0, false),
+                piece(10.0, 20.0, true),
+                piece(20.0, 25.0, false)
+            ]
+        );
+        assert_eq!(
+            split(9.9, 25.0, &KEYFRAMES),
+            vec![piece(10.0, 20.0, true), piece(20.0, 25.0, false)]
+        );
+    }
+
+    #[test]
+    fn interval_without_keyframe_is_reencoded() {
+        assert_eq!(
+            split(12.0, 18.0, &KEYFRAMES),
+            vec![piece(12.0, 18.0, false)]
+        );
+    }
+
+    #[test]
+    fn short_interval_is_not_collapsed() {
+        // Both ends would snap to the keyframe at 10.1
+        assert_eq!(
+            split(10.0, 10.3, &[0.0, 10.1, 20.0]),
+            vec![piece(10.0, 10.3, false)]
+        );
+        assert!(split(10.0, 10.0, &[0.0, 10.1, 20.0]).is_empty());
+    }
+
+    #[test]
+    fn copy_starts_exactly_at_keyframe() {
+        let keyframe = 36000.04;
+        assert_eq!(
+            split(36000.0, 36010.0, &[keyframe, 36020.0]),
+            vec![piece(keyframe, 36010.0, false)]
+        );
+        assert_eq!(
+            split(36000.0, 36020.2, &[keyframe, 36020.0])[0],
+            piece(keyframe, 36020.0, true)
+        );
+    }
+
+    #[test]
+    fn kept_segments_are_joined() {
+        let segment = |from: f32, to: f32, speed: f32| Segment {
+            from,
+            to,
+            speed,
+            silent: speed != 1.0,
+        };
+        let segments = vec![
+            segment(0.0, 1.0, 1.0),
+            segment(1.0, 2.5, 1.0),
+            segment(2.5, 3.0, 100.0),
+            segment(3.0, 4.0, 1.0),
+        ];
+        assert_eq!(kept_intervals(&segments), vec![(0.0, 2.5), (3.0, 4.0)]);
+    }
+
+    #[test]
+    fn cut_only_speeds() {
+        assert!(is_cut_only(&[1.0, 100.0]));
+        assert!(is_cut_only(&[1.0, 1.0]));
+        assert!(!is_cut_only(&[1.0, 5.0]));
+        assert!(!is_cut_only(&[1.5, 100.0]));
+    }
+
+    #[test]
+    fn encoders_for_mpegts_codecs() {
+        assert_eq!(encoder_for("h264"), Some("libx264"));
+        assert_eq!(encoder_for("opus"), Some("libopus"));
+        assert_eq!(encoder_for("vp9"), None);
+        assert_eq!(encoder_for("vorbis"), None);
+    }
+}
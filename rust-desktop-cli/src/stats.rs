@@ -0,0 +1,272 @@
+use serde::Serialize;
+
+use crate::timeline::Segment;
+
+/// Upper bounds (in seconds) of segment length histogram buckets.
+/// Last bucket contains everything longer than the last bound.
+const HISTOGRAM_BOUNDS: [f32; 7] = [0.5, 1.0, 2.0, 5.0, 10.0, 30.0, 60.0];
+/// Width of the longest histogram bar, in characters.
+const HISTOGRAM_WIDTH: usize = 40;
+
+/// Statistics about summarized video. All durations are in seconds.
+#[derive(Serialize)]
+pub struct Stats<'a> {
+    pub duration: f32,
+    pub summarized_duration: f32,
+    pub time_saved: f32,
+    pub silent_percentage: f32,
+    pub classes: Vec<ClassStats>,
+    pub histogram: Vec<HistogramBucket>,
+    pub segments: &'a [Segment],
+}
+
+/// Statistics of silent or loud segments.
+#[derive(Serialize)]
+pub struct ClassStats {
+    pub class: &'static str,
+    pub count: usize,
+    pub total_length: f32,
+    pub mean_length: f32,
+    pub summarized_length: f32,
+}
+
+/// Number of segments that are at least `from` and less than `to` seconds long.
+/// Last bucket doesn't have an upper bound.
+#[derive(Serialize)]
+pub struct HistogramBucket {
+    pub from: f32,
+    pub to: Option<f32>,
+    pub count: usize,
+}
+
+impl<'a> Stats<'a> {
+    /// Compute statistics from the actual segments, so every segment
+    /// is counted with its own speed.
+    pub fn new(segments: &'a [Segment]) -> Stats<'a> {
+        let duration: f32 = segments.iter().map(|s| s.duration()).sum();
+        let summarized_duration: f32 = segments.iter().map(|s| s.summarized_duration()).sum();
+        let classes = vec![
+            ClassStats::new("silent", segments.iter().filter(|s| s.silent)),
+            ClassStats::new("loud", segments.iter().filter(|s| !s.silent)),
+        ];
+        let silent_percentage = if duration > 0.0 {
+            classes[0].total_length / duration * 100.0
+        } else {
+            0.0
+        };
+
+        let mut histogram: Vec<HistogramBucket> = Vec::new();
+        let mut bucket_from = 0.0;
+        for bound in HISTOGRAM_BOUNDS.iter() {
+            histogram.push(HistogramBucket {
+                from: bucket_from,
+                to: Some(*bound),
+                count: 0,
+            });
+            bucket_from = *bound;
+        }
+        histogram.push(HistogramBucket {
+            from: bucket_from,
+            to: None,
+            count: 0,
+        });
+        for segment in segments {
+            let bucket = HISTOGRAM_BOUNDS
+                .iter()
+                .position(|bound| segment.duration() < *bound)
+                .unwrap_or(HISTOGRAM_BOUNDS.len());
+            histogram[bucket].count += 1;
+        }
+
+        Stats {
+            duration,
+            summarized_duration,
+            time_saved: duration - summarized_duration,
+            silent_percentage,
+            classes,
+            histogram,
+            segments,
+        }
+    }
+
+    /// Print statistics in human readable form.
+    pub fn print_text(&self) {
+        println!("{:.2}% of video is silent.", self.silent_percentage);
+        println!(
+            "Video is {:.1} minutes long, summarized video will be {:.1} minutes long.",
+            self.duration / 60.0,
+            self.summarized_duration / 60.0
+        );
+        println!(
+            "Estimated time saved is {:.1} minutes ({:.2}%).",
+            self.time_saved / 60.0,
+            if self.duration > 0.0 {
+                self.time_saved / self.duration * 100.0
+            } else {
+                0.0
+            }
+        );
+
+        println!();
+        println!(
+            "{:<8}{:>8}{:>14}{:>14}{:>16}",
+            "class", "count", "total (s)", "mean (s)", "summarized (s)"
+        );
+        for class in &self.classes {
+            println!(
+                "{:<8}{:>8}{:>14.1}{:>14.2}{:>16.1}",
+                class.class,
+                class.count,
+                class.total_length,
+                class.mean_length,
+                class.summarized_length
+            );
+        }
+
+        println!();
+        println!("Segment lengths:");
+        let max_count = self.histogram.iter().map(|b| b.count).max().unwrap_or(0);
+        for bucket in &self.histogram {
+            let label = match bucket.to {
+                Some(to) => format!("{}-{}s", bucket.from, to),
+                None => format!("{}s+", bucket.from),
+            };
+            // Round up, so non-empty buckets are always visible
+            let bar_length = (bucket.count * HISTOGRAM_WIDTH).div_ceil(max_count.max(1));
+            println!(
+                "{:>9} {:>6} {}",
+                label,
+                bucket.count,
+                "#".repeat(bar_length)
+            );
+        }
+    }
+
+    /// Print statistics, including all the segments, as JSON.
+    pub fn print_json(&self) {
+        println!(
+            "{}",
+            serde_json::to_string_pretty(self).expect("Failed to serialize stats.")
+        );
+    }
+}
+
+impl ClassStats {
+    fn new<'a>(class: &'static str, segments: impl Iterator<Item = &'a Segment>) -> ClassStats {
+        let mut count = 0;
+        let mut total_length = 0.0;
+        let mut summarized_length = 0.0;
+        for segment in segments {
+            count += 1;
+            total_length += segment.duration();
+            summarized_length += segment.summarized_duration();
+        }
+        ClassStats {
+            class,
+            count,
+            total_length,
+            mean_length: if count > 0 {
+                total_length / count as f32
+            } else {
+                0.0
+            },
+            summarized_length,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn segment(from: f32, to: f32, speed: f32, silent: bool) -> Segment {
+        Segment {
+            from,
+            to,
+            speed,
+            silent,
+        }
+    }
+
+    fn segments() -> Vec<Segment> {
+        vec![
+            segment(0.0, 4.0, 2.0, false),
+            segment(4.0, 4.5, 4.0, true),
+            segment(4.5, 16.0, 100.0, true),
+            segment(16.0, 24.0, 2.0, false),
+        ]
+    }
+
+    #[test]
+    fn durations_and_percentage() {
+        let segments = segments();
+        let stats = Stats::new(&segments);
+        assert_eq!(stats.duration, 24.0);
+        // Dropped segment doesn't add anything
+        assert_eq!(stats.summarized_duration, 2.0 + 0.125 + 4.0);
+        assert_eq!(stats.time_saved, 24.0 - 6.125);
+        assert_eq!(stats.silent_percentage, 50.0);
+    }
+
+    #[test]
+    fn classes() {
+        let segments = segments();
+        let stats = Stats::new(&segments);
+        let classes: Vec<(&str, usize, f32, f32, f32)> = stats
+            .classes
+            .iter()
+            .map(|c| {
+                (
+                    c.class,
+                    c.count,
+                    c.total_length,
+                    c.mean_length,
+                    c.summarized_length,
+                )
+            })
+            .collect();
+        assert_eq!(
+            classes,
+            vec![("silent", 2, 12.0, 6.0, 0.125), ("loud", 2, 12.0, 6.0, 6.0)]
+        );
+    }
+
+    #[test]
+    fn histogram() {
+        let mut segments = segments();
+        segments.push(segment(24.0, 24.2, 2.0, true));
+        segments.push(segment(24.2, 124.2, 2.0, false));
+        let stats = Stats::new(&segments);
+        let buckets: Vec<(f32, Option<f32>, usize)> = stats
+            .histogram
+            .iter()
+            .map(|b| (b.from, b.to, b.count))
+            .collect();
+        // Bucket includes its lower bound, so 0.5 s is in the second one
+        assert_eq!(
+            buckets,
+            vec![
+                (0.0, Some(0.5), 1),
+                (0.5, Some(1.0), 1),
+                (1.0, Some(2.0), 0),
+                (2.0, Some(5.0), 1),
+                (5.0, Some(10.0), 1),
+                (10.0, Some(30.0), 1),
+                (30.0, Some(60.0), 0),
+                (60.0, None, 1),
+            ]
+        );
+    }
+
+    #[test]
+    fn no_segments() {
+        let stats = Stats::new(&[]);
+        assert_eq!(stats.duration, 0.0);
+        assert_eq!(stats.silent_percentage, 0.0);
+        assert!(stats
+            .classes
+            .iter()
+            .all(|c| c.count == 0 && c.mean_length == 0.0));
+        assert!(stats.histogram.iter().all(|b| b.count == 0));
+    }
+}
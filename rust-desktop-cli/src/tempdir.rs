@@ -0,0 +1,28 @@
+use guid_create::GUID;
+
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// Temporary directory that is removed when dropped, so it's not left
+/// behind even if we return early or panic.
+pub struct TempDir {
+    path: PathBuf,
+}
+impl TempDir {
+    pub fn new() -> TempDir {
+        let path = std::env::temp_dir().join(GUID::rand().to_string());
+        fs::DirBuilder::new()
+            .create(&path)
+            .expect("Failed to create tmp directory.");
+        TempDir { path }
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+}
+impl Drop for TempDir {
+    fn drop(&mut self) {
+        let _ = fs::remove_dir_all(&self.path);
+    }
+}
@@ -0,0 +1,47 @@
+use serde::Serialize;
+
+use crate::{SpeedupRange, VideoMetadata};
+
+/// Part of the video, in seconds, that is played at given speed.
+#[derive(Serialize, Clone)]
+pub struct Segment {
+    pub from: f32,
+    pub to: f32,
+    pub speed: f32,
+    pub silent: bool,
+}
+impl Segment {
+    /// Length of the segment in the source video.
+    pub fn duration(&self) -> f32 {
+        self.to - self.from
+    }
+
+    /// Segments with speed higher than or equal to 100 are dropped completely.
+    pub fn is_dropped(&self) -> bool {
+        self.speed >= 100.0
+    }
+
+    /// Length of the segment after it's sped up.
+    pub fn summarized_duration(&self) -> f32 {
+        if self.is_dropped() {
+            0.0
+        } else {
+            self.duration() / self.speed
+        }
+    }
+}
+
+/// Convert speedup ranges, which are in video frames, to segments in seconds.
+/// Empty ranges are left out.
+pub fn segments_from_ranges(ranges: &[SpeedupRange], metadata: &VideoMetadata) -> Vec<Segment> {
+    ranges
+        .iter()
+        .filter(|range| range.frame_to - range.frame_from != 0)
+        .map(|range| Segment {
+            from: range.frame_from as f32 / metadata.fps,
+            to: range.frame_to as f32 / metadata.fps,
+            speed: range.speedup_rate,
+            silent: range.silent,
+        })
+        .collect()
+}
@@ -0,0 +1,293 @@
+use serde::{Deserialize, Serialize};
+
+use std::collections::{BTreeMap, HashMap};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::thread;
+use std::time::{Duration, Instant, UNIX_EPOCH};
+
+use crate::batch;
+use crate::stats::Stats;
+use crate::Cli;
+
+/// Name of the ledger file in output directory, unless user sets another one.
+const LEDGER_FILENAME: &str = ".video-summarizer-ledger.json";
+
+/// Files that were already summarized, so they are not summarized again
+/// after restart. Files are identified by path, size and modification time,
+/// so a file that is replaced is summarized again.
+#[derive(Serialize, Deserialize, Default)]
+struct Ledger {
+    files: BTreeMap<String, LedgerEntry>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct LedgerEntry {
+    size: u64,
+    modified: u64,
+    output: String,
+    status: String,
+}
+impl LedgerEntry {
+    /// Whether the entry is about the file as it is now. Files are summarized
+    /// again once they change, no matter if they failed or not. Failed files
+    /// are not retried until then, as they would most likely fail again.
+    fn is_current(&self, state: FileState) -> bool {
+        self.size == state.size && self.modified == state.modified
+    }
+}
+
+/// Size and modification time of a file, when it was last seen.
+#[derive(PartialEq, Clone, Copy)]
+struct FileState {
+    size: u64,
+    modified: u64,
+}
+
+/// Watch directory for new videos, and summarize them into output directory
+/// once they stop growing. Stats of every video are written next to it
+/// as JSON. This runs until it's killed.
+pub fn watch(args: &Cli, directory: &Path, interval: u64, settle: u64, ledger_path: Option<&Path>) {
+    if !args.output.as_os_str().is_empty()
+        || args.plot.is_some()
+        || args.report.is_some()
+        || args.mpv_script.is_some()
+    {
+        eprintln!("Options -o, --plot, --report and --mpv cannot be used when watching directory. Use --output-dir instead.");
+        return;
+    }
+    if !directory.is_dir() {
+        eprintln!("{} is not a directory.", directory.display());
+        return;
+    }
+    let output_directory = args
+        .output_dir
+        .clone()
+        .unwrap_or_else(|| directory.join("summarized"));
+    fs::create_dir_all(&output_directory).expect("Failed to create output directory.");
+    let ledger_path = ledger_path
+        .map(Path::to_path_buf)
+        .unwrap_or_else(|| output_directory.join(LEDGER_FILENAME));
+    let mut ledger = load_ledger(&ledger_path);
+    // Output directory might be inside the watched one, don't summarize outputs again
+    let output_directory_absolute = fs::canonicalize(&output_directory)
+        .expect("Failed to get absolute path of output directory.");
+
+    if !args.quiet {
+        eprintln!(
+            "Watching {} for new videos, writing them to {}",
+            directory.display(),
+            output_directory.display()
+        );
+    }
+
+    // When was each file first seen with its current size
+    let mut unchanged_since: HashMap<PathBuf, (FileState, Instant)> = HashMap::new();
+    loop {
+        let mut files: Vec<PathBuf> = Vec::new();
+        if let Err(e) = batch::find_files(directory, Path::new(""), &mut files) {
+            eprintln!("Failed to read directory {}: {}", directory.display(), e);
+        }
+        let mut seen: Vec<PathBuf> = Vec::new();
+        for relative_path in files {
+            if !batch::matches_filter(&args.glob, &relative_path) {
+                continue;
+            }
+            let input = directory.join(&relative_path);
+            match fs::canonicalize(&input) {
+                Ok(input) if input.starts_with(&output_directory_absolute) => continue,
+                Err(_) => continue,
+                _ => {}
+            }
+            let state = match file_state(&input) {
+                Some(state) => state,
+                None => continue,
+            };
+            let key = input.to_string_lossy().to_string();
+            if let Some(entry) = ledger.files.get(&key) {
+                if entry.is_current(state) {
+                    continue;
+                }
+            }
+            seen.push(input.clone());
+
+            // Wait until the file stops growing
+            let since = match unchanged_since.get(&input) {
+                Some((last_state, since)) if *last_state == state => *since,
+                _ => {
+                    unchanged_since.insert(input.clone(), (state, Instant::now()));
+                    continue;
+                }
+            };
+            if since.elapsed() < Duration::from_secs(settle) {
+                continue;
+            }
+
+            let output = match relative_path.parent() {
+                Some(parent) => output_directory.join(parent),
+                None => output_directory.clone(),
+            }
+            .join(batch::output_name(args, &input));
+            if !args.quiet {
+                eprintln!("Summarizing {}", input.display());
+            }
+            let status = match batch::summarize_isolated(args, &input, &output) {
+                Ok(summary) => {
+                    write_sidecar(&output, &summary);
+                    if !args.quiet {
+                        eprintln!("Summarized {} to {}", input.display(), output.display());
+                    }
+                    String::from("ok")
+                }
+                Err(e) => {
+                    eprintln!("Failed to summarize {}: {}", input.display(), e);
+                    format!("failed: {}", e)
+                }
+            };
+            ledger.files.insert(
+                key,
+                LedgerEntry {
+                    size: state.size,
+                    modified: state.modified,
+                    output: output.to_string_lossy().to_string(),
+                    status,
+                },
+            );
+            save_ledger(&ledger_path, &ledger);
+            unchanged_since.remove(&input);
+        }
+        // Forget files that were removed in the meantime
+        unchanged_since.retain(|input, _| seen.contains(input));
+
+        thread::sleep(Duration::from_secs(interval));
+    }
+}
+
+fn file_state(path: &Path) -> Option<FileState> {
+    let metadata = fs::metadata(path).ok()?;
+    let modified = metadata
+        .modified()
+        .ok()?
+        .duration_since(UNIX_EPOCH)
+        .ok()?
+        .as_secs();
+    Some(FileState {
+        size: metadata.len(),
+        modified,
+    })
+}
+
+/// Write stats of summarized video to "<output>.json".
+fn write_sidecar(output: &Path, summary: &batch::Summary) {
+    let mut sidecar_path = output.as_os_str().to_owned();
+    sidecar_path.push(".json");
+    let stats = Stats::new(&summary.segments);
+    let json = serde_json::to_string_pretty(&stats).expect("Failed to serialize stats.");
+    if let Err(e) = fs::write(&sidecar_path, json) {
+        eprintln!(
+            "Failed to write stats to {}: {}",
+            sidecar_path.to_string_lossy(),
+            e
+        );
+    }
+}
+
+/// Read ledger from previous runs. Ledger that can't be read is moved
+/// aside to "<ledger>.corrupt", and we start over with an empty one.
+fn load_ledger(path: &Path) -> Ledger {
+    if !path.exists() {
+        return Ledger::default();
+    }
+    let ledger = fs::read_to_string(path)
+        .map_err(|e| e.to_string())
+        .and_then(|contents| serde_json::from_str(&contents).map_err(|e| e.to_string()));
+    match ledger {
+        Ok(ledger) => ledger,
+        Err(e) => {
+            let mut corrupt_path = path.as_os_str().to_owned();
+            corrupt_path.push(".corrupt");
+            match fs::rename(path, &corrupt_path) {
+                Ok(()) => eprintln!(
+                    "Failed to read ledger {} ({}), moved it to {}. All videos will be summarized again.",
+                    path.display(),
+                    e,
+                    corrupt_path.to_string_lossy()
+                ),
+                Err(_) => eprintln!(
+                    "Failed to read ledger {} ({}). All videos will be summarized again.",
+                    path.display(),
+                    e
+                ),
+            }
+            Ledger::default()
+        }
+    }
+}
+
+/// Write ledger to temporary file first, so it's not left
+/// half-written if we're killed.
+fn save_ledger(path: &Path, ledger: &Ledger) {
+    let mut temporary_path = path.as_os_str().to_owned();
+    temporary_path.push(".tmp");
+    fs::write(
+        &temporary_path,
+        serde_json::to_string_pretty(ledger).expect("Failed to serialize ledger."),
+    )
+    .expect("Failed to write ledger.");
+    fs::rename(&temporary_path, path).expect("Failed to write ledger.");
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn entry(status: &str) -> LedgerEntry {
+        LedgerEntry {
+            size: 100,
+            modified: 1000,
+            output: String::from("out.mp4"),
+            status: String::from(status),
+        }
+    }
+
+    #[test]
+    fn changed_files_are_summarized_again() {
+        let unchanged = FileState {
+            size: 100,
+            modified: 1000,
+        };
+        let resized = FileState {
+            size: 200,
+            modified: 1000,
+        };
+        let touched = FileState {
+            size: 100,
+            modified: 2000,
+        };
+        for status in &["ok", "failed: Source video has no audio"] {
+            assert!(entry(status).is_current(unchanged));
+            assert!(!entry(status).is_current(resized));
+            assert!(!entry(status).is_current(touched));
+        }
+    }
+
+    #[test]
+    fn corrupt_ledger_is_moved_aside() {
+        let directory = std::env::temp_dir().join(format!(
+            "video-summarizer-watch-test-{}",
+            std::process::id()
+        ));
+        fs::create_dir_all(&directory).unwrap();
+        let path = directory.join(LEDGER_FILENAME);
+        fs::write(&path, "{\"files\": {").unwrap();
+
+        let ledger = load_ledger(&path);
+        let moved = directory.join(format!("{}.corrupt", LEDGER_FILENAME));
+        let (exists, moved_exists) = (path.exists(), moved.exists());
+        fs::remove_dir_all(&directory).unwrap();
+
+        assert!(ledger.files.is_empty());
+        assert!(!exists);
+        assert!(moved_exists);
+    }
+}
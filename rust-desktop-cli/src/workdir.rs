@@ -0,0 +1,68 @@
+use std::fs::File;
+use std::io::{Read, Seek, SeekFrom};
+use std::path::{Path, PathBuf};
+
+/// How much of the beginning and of the end of the source video is hashed.
+const HASHED_BYTES: u64 = 1024 * 1024;
+/// Change this when parts in fast mode are made differently,
+/// so parts made by older versions are not reused.
+const PARTS_VERSION: &str = "2";
+
+/// Directory where fast mode keeps parts of the video. It's named after
+/// the source video and parameters that change the parts, so when the same
+/// video is summarized again with the same parameters (for example after
+/// being interrupted), parts that are already done are reused.
+///
+/// The source video is identified by its size and contents of its beginning
+/// and end, so it doesn't need to be read whole.
+pub fn fast_mode_directory(input: &Path, parameters: &[String]) -> PathBuf {
+    let mut hash = Fnv1a::new();
+    hash.write(PARTS_VERSION.as_bytes());
+    for parameter in parameters {
+        hash.write(parameter.as_bytes());
+        hash.write(&[0]);
+    }
+
+    let mut file = File::open(input).expect("Failed to open input file.");
+    let size = file
+        .metadata()
+        .expect("Failed to get size of input file.")
+        .len();
+    hash.write(&size.to_le_bytes());
+    let mut buffer: Vec<u8> = Vec::new();
+    file.by_ref()
+        .take(HASHED_BYTES)
+        .read_to_end(&mut buffer)
+        .expect("Failed to read input file.");
+    if size > HASHED_BYTES {
+        // Don't hash the same bytes twice if the video is small
+        let end_start = (size - HASHED_BYTES).max(HASHED_BYTES);
+        file.seek(SeekFrom::Start(end_start))
+            .expect("Failed to read input file.");
+        file.read_to_end(&mut buffer)
+            .expect("Failed to read input file.");
+    }
+    hash.write(&buffer);
+
+    std::env::temp_dir().join(format!("video-summarizer-{:016x}", hash.finish()))
+}
+
+/// FNV-1a hash. Unlike the standard library hasher, it is guaranteed
+/// to stay the same across versions, so the directory name does too.
+struct Fnv1a(u64);
+impl Fnv1a {
+    fn new() -> Fnv1a {
+        Fnv1a(0xcbf2_9ce4_8422_2325)
+    }
+
+    fn write(&mut self, bytes: &[u8]) {
+        for byte in bytes {
+            self.0 ^= u64::from(*byte);
+            self.0 = self.0.wrapping_mul(0x0100_0000_01b3);
+        }
+    }
+
+    fn finish(&self) -> u64 {
+        self.0
+    }
+}